@@ -0,0 +1,141 @@
+// AES-256-GCM sealing of 4096-byte chunks, shared by tuffd and the UEFI loader.
+//
+// On-disk chunk: [nonce 12][tag 16][ciphertext 4068]
+// The chunk address (hw_id, chunk_id) is bound in as associated data, so a
+// chunk copied to another location fails authentication.
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, Tag};
+
+pub const CHUNK_SIZE: usize = 4096;
+pub const NONCE_LEN: usize = 12;
+pub const TAG_LEN: usize = 16;
+pub const OVERHEAD: usize = NONCE_LEN + TAG_LEN;
+pub const PLAINTEXT_LEN: usize = CHUNK_SIZE - OVERHEAD;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CipherError {
+    /// Plaintext or output buffer has the wrong size.
+    Length,
+    /// Tag mismatch: wrong key, wrong address or tampered chunk.
+    Authentication,
+}
+
+pub struct ChunkCipher {
+    cipher: Aes256Gcm,
+}
+
+impl ChunkCipher {
+    pub fn new(key: &[u8; 32]) -> Self {
+        Self { cipher: Aes256Gcm::new(key.into()) }
+    }
+
+    /// Encrypts `plain` (`PLAINTEXT_LEN` bytes) into `sealed` (`CHUNK_SIZE` bytes).
+    ///
+    /// `nonce` must never repeat under one key; callers draw it from an RNG
+    /// since a chunk address is rewritten many times.
+    pub fn seal(
+        &self,
+        hw_id: u64,
+        chunk_id: u64,
+        nonce: &[u8; NONCE_LEN],
+        plain: &[u8],
+        sealed: &mut [u8],
+    ) -> Result<(), CipherError> {
+        if plain.len() != PLAINTEXT_LEN || sealed.len() != CHUNK_SIZE {
+            return Err(CipherError::Length);
+        }
+        let (head, body) = sealed.split_at_mut(OVERHEAD);
+        body.copy_from_slice(plain);
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(Nonce::from_slice(nonce), &address_aad(hw_id, chunk_id), body)
+            .map_err(|_| CipherError::Length)?;
+        head[..NONCE_LEN].copy_from_slice(nonce);
+        head[NONCE_LEN..].copy_from_slice(&tag);
+        Ok(())
+    }
+
+    /// Authenticates and decrypts `sealed` into `plain`. `plain` is left zeroed on failure.
+    pub fn open(
+        &self,
+        hw_id: u64,
+        chunk_id: u64,
+        sealed: &[u8],
+        plain: &mut [u8],
+    ) -> Result<(), CipherError> {
+        if sealed.len() != CHUNK_SIZE || plain.len() != PLAINTEXT_LEN {
+            return Err(CipherError::Length);
+        }
+        let (nonce, rest) = sealed.split_at(NONCE_LEN);
+        let (tag, body) = rest.split_at(TAG_LEN);
+        plain.copy_from_slice(body);
+        let res = self.cipher.decrypt_in_place_detached(
+            Nonce::from_slice(nonce),
+            &address_aad(hw_id, chunk_id),
+            plain,
+            Tag::from_slice(tag),
+        );
+        if res.is_err() {
+            plain.fill(0);
+            return Err(CipherError::Authentication);
+        }
+        Ok(())
+    }
+}
+
+fn address_aad(hw_id: u64, chunk_id: u64) -> [u8; 16] {
+    let mut aad = [0u8; 16];
+    aad[..8].copy_from_slice(&hw_id.to_le_bytes());
+    aad[8..].copy_from_slice(&chunk_id.to_le_bytes());
+    aad
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sealed_chunk(cipher: &ChunkCipher, plain: &[u8]) -> [u8; CHUNK_SIZE] {
+        let mut sealed = [0u8; CHUNK_SIZE];
+        cipher.seal(7, 100, &[9u8; NONCE_LEN], plain, &mut sealed).unwrap();
+        sealed
+    }
+
+    #[test]
+    fn seal_open_roundtrip() {
+        let cipher = ChunkCipher::new(&[1u8; 32]);
+        let plain = [0xC3u8; PLAINTEXT_LEN];
+        let sealed = sealed_chunk(&cipher, &plain);
+        assert_ne!(&sealed[OVERHEAD..], &plain[..]);
+
+        let mut out = [0u8; PLAINTEXT_LEN];
+        cipher.open(7, 100, &sealed, &mut out).unwrap();
+        assert_eq!(out, plain);
+    }
+
+    #[test]
+    fn open_rejects_tamper_relocation_and_wrong_key() {
+        let cipher = ChunkCipher::new(&[1u8; 32]);
+        let plain = [0x11u8; PLAINTEXT_LEN];
+        let mut sealed = sealed_chunk(&cipher, &plain);
+        let mut out = [0u8; PLAINTEXT_LEN];
+
+        assert_eq!(cipher.open(7, 101, &sealed, &mut out), Err(CipherError::Authentication));
+        assert_eq!(cipher.open(8, 100, &sealed, &mut out), Err(CipherError::Authentication));
+        let other = ChunkCipher::new(&[2u8; 32]);
+        assert_eq!(other.open(7, 100, &sealed, &mut out), Err(CipherError::Authentication));
+
+        sealed[CHUNK_SIZE - 1] ^= 1;
+        assert_eq!(cipher.open(7, 100, &sealed, &mut out), Err(CipherError::Authentication));
+        assert!(out.iter().all(|&b| b == 0));
+    }
+
+    #[test]
+    fn seal_rejects_wrong_lengths() {
+        let cipher = ChunkCipher::new(&[1u8; 32]);
+        let mut sealed = [0u8; CHUNK_SIZE];
+        assert_eq!(
+            cipher.seal(0, 0, &[0u8; NONCE_LEN], &[0u8; 16], &mut sealed),
+            Err(CipherError::Length)
+        );
+    }
+}
@@ -0,0 +1,288 @@
+// Passphrase-wrapped Master Key files (`TUFF_KEYS/<system_uuid>.key`).
+//
+// Layout (v1), all integers little-endian:
+//   magic "TUFFKEY" | version u8 = 1
+//   argon2id m_cost_kib u32 | t_cost u32 | p_cost u32
+//   salt [16] | nonce [12]
+//   uuid_len u8 | system UUID (ASCII)
+//   MK fingerprint [32]
+//   wrapped MK [32] | tag [16]
+// Everything before the wrapped MK is the AES-256-GCM associated data, so the
+// KDF parameters, the bound UUID and the fingerprint cannot be altered.
+// Legacy key files are the bare 32-byte MK.
+use alloc::vec::Vec;
+use aes_gcm::aead::AeadInPlace;
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce, Tag};
+use argon2::{Algorithm, Argon2, Params, Version};
+use zeroize::Zeroizing;
+
+use crate::key_manager::{mk_fingerprint, FINGERPRINT_LEN, MASTER_KEY_LEN};
+
+pub const MAGIC: &[u8; 7] = b"TUFFKEY";
+pub const VERSION: u8 = 1;
+pub const SALT_LEN: usize = 16;
+pub const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyFileError {
+    /// Truncated or malformed file.
+    Format,
+    /// Newer format than this build understands.
+    UnsupportedVersion(u8),
+    /// KDF parameters above `KdfParams::MAX` or rejected by Argon2.
+    Kdf,
+    /// Wrong passphrase or tampered file.
+    Authentication,
+    /// The file is bound to another machine.
+    UuidMismatch,
+    /// The unwrapped key does not match the recorded fingerprint.
+    FingerprintMismatch,
+}
+
+/// Argon2id cost parameters stored in the file header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    pub m_cost_kib: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    /// Ceilings enforced before deriving: the header is read before it can be
+    /// authenticated, so a forged file must not make the KDF allocate or run
+    /// without bound.
+    pub const MAX: KdfParams = KdfParams { m_cost_kib: 1024 * 1024, t_cost: 10, p_cost: 4 };
+
+    fn check(&self) -> Result<(), KeyFileError> {
+        let max = Self::MAX;
+        if self.m_cost_kib > max.m_cost_kib || self.t_cost > max.t_cost || self.p_cost > max.p_cost {
+            return Err(KeyFileError::Kdf);
+        }
+        Ok(())
+    }
+}
+
+impl Default for KdfParams {
+    /// 64 MiB, 3 passes: a few hundred milliseconds on the target hardware.
+    fn default() -> Self {
+        Self { m_cost_kib: 64 * 1024, t_cost: 3, p_cost: 1 }
+    }
+}
+
+/// Authenticated metadata read from a wrapped key file, before unwrapping.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyFileHeader<'a> {
+    pub params: KdfParams,
+    pub salt: &'a [u8],
+    pub nonce: &'a [u8],
+    pub system_uuid: &'a str,
+    pub fingerprint: &'a [u8],
+}
+
+/// True if `data` starts with the wrapped-format magic (any version).
+pub fn is_wrapped(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// True if `data` is a legacy raw Master Key.
+pub fn is_legacy(data: &[u8]) -> bool {
+    data.len() == MASTER_KEY_LEN && !is_wrapped(data)
+}
+
+/// Parses the header; returns it with the length of the associated data.
+pub fn parse_header(data: &[u8]) -> Result<(KeyFileHeader<'_>, usize), KeyFileError> {
+    let mut r = Reader { data, pos: 0 };
+    if r.take(MAGIC.len())? != MAGIC {
+        return Err(KeyFileError::Format);
+    }
+    let version = r.take(1)?[0];
+    if version != VERSION {
+        return Err(KeyFileError::UnsupportedVersion(version));
+    }
+    let params = KdfParams { m_cost_kib: r.u32()?, t_cost: r.u32()?, p_cost: r.u32()? };
+    params.check()?;
+    let salt = r.take(SALT_LEN)?;
+    let nonce = r.take(NONCE_LEN)?;
+    let uuid_len = r.take(1)?[0] as usize;
+    let system_uuid = core::str::from_utf8(r.take(uuid_len)?).map_err(|_| KeyFileError::Format)?;
+    let fingerprint = r.take(FINGERPRINT_LEN)?;
+    let aad_len = r.pos;
+    if data.len() != aad_len + MASTER_KEY_LEN + TAG_LEN {
+        return Err(KeyFileError::Format);
+    }
+    Ok((KeyFileHeader { params, salt, nonce, system_uuid, fingerprint }, aad_len))
+}
+
+/// Wraps `mk` under `passphrase`, bound to `system_uuid`.
+///
+/// `salt` and `nonce` must be fresh random values; callers draw them from an RNG.
+pub fn wrap(
+    mk: &[u8; MASTER_KEY_LEN],
+    passphrase: &[u8],
+    system_uuid: &str,
+    params: KdfParams,
+    salt: &[u8; SALT_LEN],
+    nonce: &[u8; NONCE_LEN],
+) -> Result<Vec<u8>, KeyFileError> {
+    let uuid_len = u8::try_from(system_uuid.len()).map_err(|_| KeyFileError::Format)?;
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.push(VERSION);
+    out.extend_from_slice(&params.m_cost_kib.to_le_bytes());
+    out.extend_from_slice(&params.t_cost.to_le_bytes());
+    out.extend_from_slice(&params.p_cost.to_le_bytes());
+    out.extend_from_slice(salt);
+    out.extend_from_slice(nonce);
+    out.push(uuid_len);
+    out.extend_from_slice(system_uuid.as_bytes());
+    out.extend_from_slice(&mk_fingerprint(mk));
+
+    let kek = derive_kek(passphrase, salt, params)?;
+    let mut body = Zeroizing::new(*mk);
+    let tag = Aes256Gcm::new(kek.as_ref().into())
+        .encrypt_in_place_detached(Nonce::from_slice(nonce), &out, body.as_mut_slice())
+        .map_err(|_| KeyFileError::Format)?;
+    out.extend_from_slice(body.as_slice());
+    out.extend_from_slice(&tag);
+    Ok(out)
+}
+
+/// Unwraps the Master Key, checking that the file is bound to `system_uuid`
+/// (compared case-insensitively, as DMI UUIDs are hex).
+pub fn unwrap(
+    data: &[u8],
+    passphrase: &[u8],
+    system_uuid: &str,
+) -> Result<Zeroizing<[u8; MASTER_KEY_LEN]>, KeyFileError> {
+    let (header, aad_len) = parse_header(data)?;
+    if !header.system_uuid.eq_ignore_ascii_case(system_uuid) {
+        return Err(KeyFileError::UuidMismatch);
+    }
+    let salt: &[u8; SALT_LEN] = header.salt.try_into().map_err(|_| KeyFileError::Format)?;
+    let kek = derive_kek(passphrase, salt, header.params)?;
+
+    let (aad, rest) = data.split_at(aad_len);
+    let (wrapped, tag) = rest.split_at(MASTER_KEY_LEN);
+    let mut mk = Zeroizing::new([0u8; MASTER_KEY_LEN]);
+    mk.copy_from_slice(wrapped);
+    Aes256Gcm::new(kek.as_ref().into())
+        .decrypt_in_place_detached(
+            Nonce::from_slice(header.nonce),
+            aad,
+            mk.as_mut_slice(),
+            Tag::from_slice(tag),
+        )
+        .map_err(|_| KeyFileError::Authentication)?;
+    if mk_fingerprint(mk.as_slice()) != header.fingerprint {
+        return Err(KeyFileError::FingerprintMismatch);
+    }
+    Ok(mk)
+}
+
+fn derive_kek(
+    passphrase: &[u8],
+    salt: &[u8; SALT_LEN],
+    params: KdfParams,
+) -> Result<Zeroizing<[u8; 32]>, KeyFileError> {
+    params.check()?;
+    let params = Params::new(params.m_cost_kib, params.t_cost, params.p_cost, Some(32))
+        .map_err(|_| KeyFileError::Kdf)?;
+    let mut kek = Zeroizing::new([0u8; 32]);
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase, salt, kek.as_mut_slice())
+        .map_err(|_| KeyFileError::Kdf)?;
+    Ok(kek)
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], KeyFileError> {
+        let end = self.pos.checked_add(n).ok_or(KeyFileError::Format)?;
+        let out = self.data.get(self.pos..end).ok_or(KeyFileError::Format)?;
+        self.pos = end;
+        Ok(out)
+    }
+
+    fn u32(&mut self) -> Result<u32, KeyFileError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    const UUID: &str = "4C4C4544-0042-3010-8052-B4C04F4E3332";
+    // Cheap parameters so the tests stay fast.
+    const FAST: KdfParams = KdfParams { m_cost_kib: 64, t_cost: 1, p_cost: 1 };
+
+    fn wrapped() -> Vec<u8> {
+        wrap(&[7u8; MASTER_KEY_LEN], b"correct horse", UUID, FAST, &[1u8; SALT_LEN], &[2u8; NONCE_LEN])
+            .unwrap()
+    }
+
+    #[test]
+    fn wrap_round_trip_and_header() {
+        let file = wrapped();
+        assert!(is_wrapped(&file));
+        assert!(!is_legacy(&file));
+        let (header, _) = parse_header(&file).unwrap();
+        assert_eq!(header.params, FAST);
+        assert_eq!(header.system_uuid, UUID);
+        assert_eq!(header.fingerprint, &mk_fingerprint(&[7u8; MASTER_KEY_LEN])[..]);
+
+        let mk = unwrap(&file, b"correct horse", &UUID.to_lowercase()).unwrap();
+        assert_eq!(*mk, [7u8; MASTER_KEY_LEN]);
+    }
+
+    #[test]
+    fn wrong_passphrase_uuid_or_tampering_is_rejected() {
+        let file = wrapped();
+        assert_eq!(unwrap(&file, b"battery staple", UUID).err(), Some(KeyFileError::Authentication));
+        assert_eq!(unwrap(&file, b"correct horse", "other").err(), Some(KeyFileError::UuidMismatch));
+
+        // Rebinding the file to another machine breaks the tag.
+        let mut moved = file.clone();
+        let at = moved.len() - MASTER_KEY_LEN - TAG_LEN - FINGERPRINT_LEN - 1;
+        moved[at] ^= 1;
+        let moved_uuid = parse_header(&moved).unwrap().0.system_uuid.to_string();
+        assert_eq!(
+            unwrap(&moved, b"correct horse", &moved_uuid).err(),
+            Some(KeyFileError::Authentication)
+        );
+
+        let mut newer = file;
+        newer[MAGIC.len()] = 2;
+        assert_eq!(parse_header(&newer).err(), Some(KeyFileError::UnsupportedVersion(2)));
+        assert!(is_legacy(&[0u8; MASTER_KEY_LEN]));
+    }
+
+    #[test]
+    fn oversized_kdf_parameters_are_rejected_before_deriving() {
+        let file = wrapped();
+        let cost_at = MAGIC.len() + 1;
+        for (offset, value) in [
+            (0, KdfParams::MAX.m_cost_kib + 1),
+            (4, KdfParams::MAX.t_cost + 1),
+            (8, KdfParams::MAX.p_cost + 1),
+            (0, u32::MAX),
+        ] {
+            let mut forged = file.clone();
+            forged[cost_at + offset..cost_at + offset + 4].copy_from_slice(&value.to_le_bytes());
+            assert_eq!(parse_header(&forged).err(), Some(KeyFileError::Kdf));
+            assert_eq!(unwrap(&forged, b"correct horse", UUID).err(), Some(KeyFileError::Kdf));
+        }
+
+        let too_slow = KdfParams { t_cost: KdfParams::MAX.t_cost + 1, ..FAST };
+        assert_eq!(
+            wrap(&[7u8; MASTER_KEY_LEN], b"pw", UUID, too_slow, &[1u8; SALT_LEN], &[2u8; NONCE_LEN]).err(),
+            Some(KeyFileError::Kdf)
+        );
+    }
+}
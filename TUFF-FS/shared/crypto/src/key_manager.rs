@@ -1,6 +1,137 @@
-pub struct KeyManager;
+// Master key custody and subkey derivation.
+//
+// The MK never encrypts anything directly: every use gets its own HKDF-SHA256
+// subkey, bound to the volume UUID so volumes sharing one MK stay independent.
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+use zeroize::Zeroizing;
+
+pub const MASTER_KEY_LEN: usize = 32;
+pub const FINGERPRINT_LEN: usize = 32;
+
+const HKDF_SALT: &[u8] = b"TUFF-FS key hierarchy v1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyError {
+    /// No master key is loaded.
+    NoKey,
+    /// Master key material has the wrong length.
+    InvalidLength(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubkeyPurpose {
+    /// Data chunk encryption (AES-256-GCM).
+    DataEncryption,
+    /// MAC over IndexChunk generations.
+    IndexAuthentication,
+    /// Per-volume key identifier that can be shown without revealing the MK fingerprint.
+    Fingerprint,
+}
+
+impl SubkeyPurpose {
+    fn label(self) -> &'static [u8] {
+        match self {
+            SubkeyPurpose::DataEncryption => b"data-encryption",
+            SubkeyPurpose::IndexAuthentication => b"index-authentication",
+            SubkeyPurpose::Fingerprint => b"fingerprint",
+        }
+    }
+}
+
+/// Canonical MK fingerprint: SHA-256(MK).
+/// Stored in `InitialChunk.mk_fingerprint` and by tuffd's fingerprint check.
+pub fn mk_fingerprint(key: &[u8]) -> [u8; FINGERPRINT_LEN] {
+    Sha256::digest(key).into()
+}
+
+/// Holds the 32-byte master key; the buffer is zeroed on drop and on `clear`.
+#[derive(Default)]
+pub struct KeyManager {
+    master: Option<Zeroizing<[u8; MASTER_KEY_LEN]>>,
+}
 
 impl KeyManager {
-    pub fn new() -> Self { Self }
-    pub fn load_key(&self, _data: &[u8]) -> bool { true }
+    pub fn new() -> Self {
+        Self { master: None }
+    }
+
+    pub fn load_key(&mut self, data: &[u8]) -> Result<(), KeyError> {
+        if data.len() != MASTER_KEY_LEN {
+            return Err(KeyError::InvalidLength(data.len()));
+        }
+        let mut key = Zeroizing::new([0u8; MASTER_KEY_LEN]);
+        key.copy_from_slice(data);
+        self.master = Some(key);
+        Ok(())
+    }
+
+    pub fn has_key(&self) -> bool {
+        self.master.is_some()
+    }
+
+    /// Wipes the master key from memory.
+    pub fn clear(&mut self) {
+        self.master = None;
+    }
+
+    pub fn fingerprint(&self) -> Result<[u8; FINGERPRINT_LEN], KeyError> {
+        Ok(mk_fingerprint(self.master()?.as_slice()))
+    }
+
+    pub fn derive_subkey(
+        &self,
+        purpose: SubkeyPurpose,
+        volume_uuid: &str,
+    ) -> Result<Zeroizing<[u8; 32]>, KeyError> {
+        let hk = Hkdf::<Sha256>::new(Some(HKDF_SALT), self.master()?.as_slice());
+        let mut okm = Zeroizing::new([0u8; 32]);
+        // info = purpose label || 0x00 || volume UUID
+        hk.expand_multi_info(&[purpose.label(), &[0u8], volume_uuid.as_bytes()], okm.as_mut_slice())
+            .map_err(|_| KeyError::InvalidLength(32))?;
+        Ok(okm)
+    }
+
+    fn master(&self) -> Result<&Zeroizing<[u8; MASTER_KEY_LEN]>, KeyError> {
+        self.master.as_ref().ok_or(KeyError::NoKey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subkeys_are_distinct_per_purpose_and_volume() {
+        let mut km = KeyManager::new();
+        km.load_key(&[5u8; MASTER_KEY_LEN]).unwrap();
+
+        let data = km.derive_subkey(SubkeyPurpose::DataEncryption, "vol-a").unwrap();
+        let index = km.derive_subkey(SubkeyPurpose::IndexAuthentication, "vol-a").unwrap();
+        let other = km.derive_subkey(SubkeyPurpose::DataEncryption, "vol-b").unwrap();
+        assert_ne!(*data, *index);
+        assert_ne!(*data, *other);
+        assert_ne!(data.as_slice(), &[5u8; 32]);
+        assert_eq!(*data, *km.derive_subkey(SubkeyPurpose::DataEncryption, "vol-a").unwrap());
+    }
+
+    #[test]
+    fn fingerprint_is_sha256_of_master_key() {
+        let mut km = KeyManager::new();
+        assert_eq!(km.fingerprint(), Err(KeyError::NoKey));
+        km.load_key(&[0u8; MASTER_KEY_LEN]).unwrap();
+        // SHA-256 of 32 zero bytes
+        assert_eq!(km.fingerprint().unwrap()[..4], [0x66, 0x68, 0x7a, 0xad]);
+    }
+
+    #[test]
+    fn clear_and_length_checks() {
+        let mut km = KeyManager::new();
+        assert_eq!(km.load_key(&[1u8; 16]), Err(KeyError::InvalidLength(16)));
+        km.load_key(&[1u8; MASTER_KEY_LEN]).unwrap();
+        assert!(km.has_key());
+        km.clear();
+        assert!(!km.has_key());
+        assert_eq!(km.derive_subkey(SubkeyPurpose::Fingerprint, "v").err(), Some(KeyError::NoKey));
+    }
 }
@@ -2,6 +2,9 @@
 extern crate alloc;
 
 #[cfg(feature = "aes")]
-pub mod aes_engine;
+pub mod chunk_cipher;
+
+#[cfg(feature = "keyfile")]
+pub mod key_file;
 
 pub mod key_manager;
@@ -0,0 +1,214 @@
+// Signed authorization to install over (or back up / restore) a disk that
+// already carries an operating system. The token sits at the root of the MK
+// USB as `TUFF_BACKUP_TOKEN.sig` and is checked against a public key embedded
+// at build time (`TUFF_TOKEN_PUBKEY`).
+//
+// Layout (v1), integers little-endian:
+//   magic "TUFFBTK" | version u8 = 1
+//   expires_at i64 (Unix seconds)
+//   operations u32 (bit set of `Operations`)
+//   serial_len u8 | target disk serial (UTF-8)
+//   Ed25519 signature [64] over every preceding byte
+use alloc::vec::Vec;
+use ed25519_dalek::SIGNATURE_LENGTH;
+
+use crate::keys::{self, DetachedError, PUBLIC_KEY_LEN};
+
+pub const TOKEN_FILE_NAME: &str = "TUFF_BACKUP_TOKEN.sig";
+pub const MAGIC: &[u8; 7] = b"TUFFBTK";
+pub const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenError {
+    /// Truncated or malformed token.
+    Format,
+    /// Newer token format than this build understands.
+    UnsupportedVersion(u8),
+    /// This build carries no (valid) token verification key.
+    NoEmbeddedKey,
+    /// Signature does not verify under the embedded key.
+    BadSignature,
+    /// The token expired at the given time.
+    Expired { expires_at: i64 },
+    /// The token was issued for another disk.
+    WrongDisk,
+    /// The token does not grant the requested operation.
+    OperationNotAllowed,
+}
+
+impl From<DetachedError> for TokenError {
+    fn from(e: DetachedError) -> Self {
+        match e {
+            DetachedError::Format => TokenError::Format,
+            DetachedError::BadKey => TokenError::NoEmbeddedKey,
+            DetachedError::BadSignature => TokenError::BadSignature,
+        }
+    }
+}
+
+/// Operations a token may grant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Operations(pub u32);
+
+impl Operations {
+    /// Overwrite a disk that carries an existing OS or filesystem.
+    pub const INSTALL: Operations = Operations(1 << 0);
+    /// Read a block image of the disk.
+    pub const BACKUP: Operations = Operations(1 << 1);
+    /// Write a block image back to the disk.
+    pub const RESTORE: Operations = Operations(1 << 2);
+
+    pub fn contains(self, other: Operations) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl core::ops::BitOr for Operations {
+    type Output = Operations;
+    fn bitor(self, rhs: Operations) -> Operations {
+        Operations(self.0 | rhs.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackupToken<'a> {
+    pub expires_at: i64,
+    pub operations: Operations,
+    pub disk_serial: &'a str,
+}
+
+impl BackupToken<'_> {
+    /// The signed part of the token, i.e. everything but the signature.
+    pub fn encode_unsigned(&self) -> Result<Vec<u8>, TokenError> {
+        let serial_len = u8::try_from(self.disk_serial.len()).map_err(|_| TokenError::Format)?;
+        let mut out = Vec::with_capacity(MAGIC.len() + 14 + self.disk_serial.len());
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.expires_at.to_le_bytes());
+        out.extend_from_slice(&self.operations.0.to_le_bytes());
+        out.push(serial_len);
+        out.extend_from_slice(self.disk_serial.as_bytes());
+        Ok(out)
+    }
+}
+
+/// Splits a token into its fields, the signed bytes and the signature,
+/// without checking the signature.
+pub fn parse(data: &[u8]) -> Result<(BackupToken<'_>, &[u8], &[u8]), TokenError> {
+    if data.len() < SIGNATURE_LENGTH {
+        return Err(TokenError::Format);
+    }
+    let (signed, signature) = data.split_at(data.len() - SIGNATURE_LENGTH);
+    let rest = signed.strip_prefix(MAGIC.as_slice()).ok_or(TokenError::Format)?;
+    let (&version, rest) = rest.split_first().ok_or(TokenError::Format)?;
+    if version != VERSION {
+        return Err(TokenError::UnsupportedVersion(version));
+    }
+    if rest.len() < 13 {
+        return Err(TokenError::Format);
+    }
+    let expires_at = i64::from_le_bytes(rest[0..8].try_into().unwrap());
+    let operations = Operations(u32::from_le_bytes(rest[8..12].try_into().unwrap()));
+    let serial_len = rest[12] as usize;
+    let serial = &rest[13..];
+    if serial.len() != serial_len {
+        return Err(TokenError::Format);
+    }
+    let disk_serial = core::str::from_utf8(serial).map_err(|_| TokenError::Format)?;
+    Ok((BackupToken { expires_at, operations, disk_serial }, signed, signature))
+}
+
+/// Parses `data` and checks its signature under `public_key`.
+pub fn verify<'a>(
+    data: &'a [u8],
+    public_key: &[u8; PUBLIC_KEY_LEN],
+) -> Result<BackupToken<'a>, TokenError> {
+    let (token, _, _) = parse(data)?;
+    keys::verify_detached(data, public_key)?;
+    Ok(token)
+}
+
+/// Full check for one use: signature, expiry (`now` in Unix seconds), target
+/// disk serial and granted operation.
+pub fn authorize<'a>(
+    data: &'a [u8],
+    public_key: &[u8; PUBLIC_KEY_LEN],
+    disk_serial: &str,
+    operation: Operations,
+    now: i64,
+) -> Result<BackupToken<'a>, TokenError> {
+    let token = verify(data, public_key)?;
+    if now >= token.expires_at {
+        return Err(TokenError::Expired { expires_at: token.expires_at });
+    }
+    if token.disk_serial.is_empty() || token.disk_serial != disk_serial {
+        return Err(TokenError::WrongDisk);
+    }
+    if !token.operations.contains(operation) {
+        return Err(TokenError::OperationNotAllowed);
+    }
+    Ok(token)
+}
+
+/// The verification key embedded in this build.
+pub fn embedded_public_key() -> Result<[u8; PUBLIC_KEY_LEN], TokenError> {
+    keys::parse_public_key(keys::BACKUP_TOKEN_KEY_HEX).ok_or(TokenError::NoEmbeddedKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    const SERIAL: &str = "WD-WCC4N1234567";
+
+    fn signed(token: &BackupToken, key: &SigningKey) -> Vec<u8> {
+        keys::sign_detached(token.encode_unsigned().unwrap(), key)
+    }
+
+    fn setup() -> (SigningKey, [u8; 32], Vec<u8>) {
+        let key = SigningKey::from_bytes(&[9u8; 32]);
+        let public = key.verifying_key().to_bytes();
+        let token = BackupToken {
+            expires_at: 2_000_000_000,
+            operations: Operations::INSTALL | Operations::BACKUP,
+            disk_serial: SERIAL,
+        };
+        let data = signed(&token, &key);
+        (key, public, data)
+    }
+
+    #[test]
+    fn valid_token_authorizes_granted_operations() {
+        let (_, public, data) = setup();
+        let token = authorize(&data, &public, SERIAL, Operations::INSTALL, 1_700_000_000).unwrap();
+        assert_eq!(token.disk_serial, SERIAL);
+        assert!(authorize(&data, &public, SERIAL, Operations::BACKUP, 1_700_000_000).is_ok());
+        assert_eq!(
+            authorize(&data, &public, SERIAL, Operations::RESTORE, 1_700_000_000).err(),
+            Some(TokenError::OperationNotAllowed)
+        );
+    }
+
+    #[test]
+    fn expiry_disk_and_signature_are_enforced() {
+        let (_, public, data) = setup();
+        assert_eq!(
+            authorize(&data, &public, SERIAL, Operations::INSTALL, 2_000_000_000).err(),
+            Some(TokenError::Expired { expires_at: 2_000_000_000 })
+        );
+        assert_eq!(
+            authorize(&data, &public, "OTHER", Operations::INSTALL, 0).err(),
+            Some(TokenError::WrongDisk)
+        );
+
+        // Widening the grant invalidates the signature.
+        let mut tampered = data.clone();
+        tampered[MAGIC.len() + 1 + 8] |= Operations::RESTORE.0 as u8;
+        assert_eq!(verify(&tampered, &public).err(), Some(TokenError::BadSignature));
+
+        let other = SigningKey::from_bytes(&[3u8; 32]).verifying_key().to_bytes();
+        assert_eq!(verify(&data, &other).err(), Some(TokenError::BadSignature));
+        assert_eq!(verify(&data[..40], &public).err(), Some(TokenError::Format));
+    }
+}
@@ -1 +1,262 @@
-pub fn validate_eu(_id: &str) -> bool { true }
+// Enrolled Unit (EU) identifiers: the printable name of one member disk of one
+// volume, i.e. the binding of a volume UUID to a device's hw_id as recorded in
+// its InitialChunk.
+//
+// Encoding: "EU-" followed by 44 Crockford base32 characters, grouped by four
+// with hyphens for reading aloud:
+//   payload (25 bytes = 40 chars): version u8 = 1 | volume UUID [16] | hw_id u64 LE
+//   check (4 chars): first 20 bits of SHA-256("TUFF-EU" | payload)
+// Parsing ignores hyphens and case, and reads I/L as 1 and O as 0.
+use alloc::string::String;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
+
+pub const PREFIX: &str = "EU-";
+pub const VERSION: u8 = 1;
+const PAYLOAD_LEN: usize = 25;
+const PAYLOAD_CHARS: usize = PAYLOAD_LEN * 8 / 5;
+const CHECK_CHARS: usize = 4;
+const ID_CHARS: usize = PAYLOAD_CHARS + CHECK_CHARS;
+const ALPHABET: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EuError {
+    /// Does not start with "EU-".
+    MissingPrefix,
+    /// Not a Crockford base32 character (position counts ID characters only).
+    InvalidCharacter { position: usize, found: char },
+    /// Fewer characters than an EU ID has.
+    Truncated { found: usize },
+    /// More characters than an EU ID has.
+    TooLong { found: usize },
+    /// Check characters do not match: mistyped or altered.
+    Checksum,
+    UnsupportedVersion(u8),
+    /// hw_id 0 is never assigned to a member disk.
+    ZeroHwId,
+    /// Volume UUID string is not 32 hex digits.
+    InvalidVolumeUuid,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EnrolledUnit {
+    pub volume_uuid: [u8; 16],
+    pub hw_id: u64,
+}
+
+impl EnrolledUnit {
+    /// Binds a volume UUID as written in the InitialChunk (hyphenated hex) to `hw_id`.
+    pub fn new(volume_uuid: &str, hw_id: u64) -> Result<Self, EuError> {
+        let digits: String = volume_uuid.chars().filter(|&c| c != '-').collect();
+        let mut uuid = [0u8; 16];
+        hex::decode_to_slice(&digits, &mut uuid).map_err(|_| EuError::InvalidVolumeUuid)?;
+        if hw_id == 0 {
+            return Err(EuError::ZeroHwId);
+        }
+        Ok(Self { volume_uuid: uuid, hw_id })
+    }
+
+    /// The volume UUID in the hyphenated form used by the InitialChunk.
+    pub fn volume_uuid_string(&self) -> String {
+        let h = hex::encode(self.volume_uuid);
+        let mut out = String::with_capacity(36);
+        for (i, range) in [0..8, 8..12, 12..16, 16..20, 20..32].into_iter().enumerate() {
+            if i > 0 {
+                out.push('-');
+            }
+            out.push_str(&h[range]);
+        }
+        out
+    }
+
+    pub fn encode(&self) -> String {
+        let payload = self.payload();
+        let mut chars = base32_encode(&payload);
+        let check = check_value(&payload);
+        for i in (0..CHECK_CHARS).rev() {
+            chars.push(ALPHABET[((check >> (i * 5)) & 0x1F) as usize]);
+        }
+
+        let mut out = String::from(PREFIX);
+        for (i, group) in chars.chunks(4).enumerate() {
+            if i > 0 {
+                out.push('-');
+            }
+            out.extend(group.iter().map(|&b| b as char));
+        }
+        out
+    }
+
+    pub fn parse(id: &str) -> Result<Self, EuError> {
+        let trimmed = id.trim();
+        let body = match trimmed.get(..PREFIX.len()) {
+            Some(p) if p.eq_ignore_ascii_case(PREFIX) => &trimmed[PREFIX.len()..],
+            _ => return Err(EuError::MissingPrefix),
+        };
+
+        let mut values = Vec::with_capacity(ID_CHARS);
+        for c in body.chars().filter(|&c| c != '-') {
+            let value = decode_char(c).ok_or(EuError::InvalidCharacter { position: values.len(), found: c })?;
+            values.push(value);
+        }
+        if values.len() < ID_CHARS {
+            return Err(EuError::Truncated { found: values.len() });
+        }
+        if values.len() > ID_CHARS {
+            return Err(EuError::TooLong { found: values.len() });
+        }
+
+        let payload = base32_decode(&values[..PAYLOAD_CHARS]);
+        let check = values[PAYLOAD_CHARS..].iter().fold(0u32, |acc, &v| (acc << 5) | v as u32);
+        if check != check_value(&payload) {
+            return Err(EuError::Checksum);
+        }
+        if payload[0] != VERSION {
+            return Err(EuError::UnsupportedVersion(payload[0]));
+        }
+        let unit = Self {
+            volume_uuid: payload[1..17].try_into().unwrap(),
+            hw_id: u64::from_le_bytes(payload[17..25].try_into().unwrap()),
+        };
+        if unit.hw_id == 0 {
+            return Err(EuError::ZeroHwId);
+        }
+        Ok(unit)
+    }
+
+    fn payload(&self) -> [u8; PAYLOAD_LEN] {
+        let mut p = [0u8; PAYLOAD_LEN];
+        p[0] = VERSION;
+        p[1..17].copy_from_slice(&self.volume_uuid);
+        p[17..25].copy_from_slice(&self.hw_id.to_le_bytes());
+        p
+    }
+}
+
+/// True if `id` is a well-formed EU ID with a matching check value.
+pub fn validate_eu(id: &str) -> bool {
+    EnrolledUnit::parse(id).is_ok()
+}
+
+fn check_value(payload: &[u8]) -> u32 {
+    let digest = Sha256::new().chain_update(b"TUFF-EU").chain_update(payload).finalize();
+    u32::from_be_bytes([0, digest[0], digest[1], digest[2]]) >> 4
+}
+
+fn decode_char(c: char) -> Option<u8> {
+    let c = match c.to_ascii_uppercase() {
+        'O' => '0',
+        'I' | 'L' => '1',
+        other => other,
+    };
+    ALPHABET.iter().position(|&a| a as char == c).map(|p| p as u8)
+}
+
+/// 25 bytes encode to exactly 40 characters, so no padding is involved.
+fn base32_encode(data: &[u8; PAYLOAD_LEN]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(ID_CHARS);
+    for group in data.chunks(5) {
+        let v = group.iter().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+        for i in (0..8).rev() {
+            out.push(ALPHABET[((v >> (i * 5)) & 0x1F) as usize]);
+        }
+    }
+    out
+}
+
+fn base32_decode(values: &[u8]) -> [u8; PAYLOAD_LEN] {
+    let mut out = [0u8; PAYLOAD_LEN];
+    for (group, chunk) in values.chunks(8).zip(out.chunks_mut(5)) {
+        let v = group.iter().fold(0u64, |acc, &c| (acc << 5) | c as u64);
+        for (i, byte) in chunk.iter_mut().enumerate() {
+            *byte = (v >> (32 - i * 8)) as u8;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::string::ToString;
+
+    const VOLUME: &str = "3f2a9c1e-7b44-4d0a-9e1f-52c6a8d0b7e3";
+
+    fn unit() -> EnrolledUnit {
+        EnrolledUnit::new(VOLUME, 0x1234_5678_9abc_def0).unwrap()
+    }
+
+    #[test]
+    fn encode_parse_round_trip() {
+        let id = unit().encode();
+        assert!(id.starts_with(PREFIX));
+        assert_eq!(id.len(), PREFIX.len() + ID_CHARS + ID_CHARS / 4 - 1);
+        assert_eq!(EnrolledUnit::parse(&id), Ok(unit()));
+        assert_eq!(unit().volume_uuid_string(), VOLUME);
+        assert!(validate_eu(&id));
+
+        // Hyphens, case and ambiguous letters do not matter.
+        let relaxed = ["eu-", &id[PREFIX.len()..].to_lowercase().replace('-', "")].concat();
+        assert_eq!(EnrolledUnit::parse(&relaxed), Ok(unit()));
+        let ambiguous = id.replace('0', "O").replace('1', "l");
+        assert_eq!(EnrolledUnit::parse(&ambiguous), Ok(unit()));
+    }
+
+    #[test]
+    fn malformed_ids_are_rejected() {
+        let id = unit().encode();
+        assert_eq!(EnrolledUnit::parse(&id[3..]), Err(EuError::MissingPrefix));
+        assert_eq!(EnrolledUnit::parse(""), Err(EuError::MissingPrefix));
+        assert!(!validate_eu("anything"));
+
+        let mut bad = id.clone();
+        bad.replace_range(3..4, "U");
+        assert_eq!(EnrolledUnit::parse(&bad), Err(EuError::InvalidCharacter { position: 0, found: 'U' }));
+        assert_eq!(
+            EnrolledUnit::parse(&[&id, "*"].concat()),
+            Err(EuError::InvalidCharacter { position: ID_CHARS, found: '*' })
+        );
+        assert_eq!(EnrolledUnit::parse(&[&id, "0"].concat()), Err(EuError::TooLong { found: ID_CHARS + 1 }));
+        assert_eq!(EnrolledUnit::new("not-a-uuid", 1), Err(EuError::InvalidVolumeUuid));
+        assert_eq!(EnrolledUnit::new(VOLUME, 0), Err(EuError::ZeroHwId));
+    }
+
+    #[test]
+    fn truncated_ids_are_rejected() {
+        let id = unit().encode();
+        assert_eq!(EnrolledUnit::parse(&id[..id.len() - 1]), Err(EuError::Truncated { found: ID_CHARS - 1 }));
+        // Dropping a whole group and its hyphen.
+        assert_eq!(EnrolledUnit::parse(&id[..id.len() - 5]), Err(EuError::Truncated { found: ID_CHARS - 4 }));
+        assert_eq!(EnrolledUnit::parse("EU-"), Err(EuError::Truncated { found: 0 }));
+    }
+
+    #[test]
+    fn tampered_ids_fail_the_check() {
+        let id = unit().encode();
+        // Every single-character substitution in the payload or check is caught.
+        let positions: Vec<usize> = id.char_indices().skip(3).filter(|&(_, c)| c != '-').map(|(i, _)| i).collect();
+        for &pos in &positions {
+            let original = id.as_bytes()[pos];
+            let replacement = if original == b'Z' { 'Y' } else { 'Z' };
+            let mut tampered = id.clone();
+            tampered.replace_range(pos..pos + 1, &replacement.to_string());
+            assert_eq!(EnrolledUnit::parse(&tampered), Err(EuError::Checksum), "position {}", pos);
+        }
+
+        // Swapping two adjacent groups is caught too.
+        let mut groups: Vec<&str> = id[3..].split('-').collect();
+        groups.swap(2, 3);
+        assert_eq!(EnrolledUnit::parse(&["EU-", &groups.join("-")].concat()), Err(EuError::Checksum));
+
+        // A validly checksummed ID from another version is reported as such.
+        let mut payload = unit().payload();
+        payload[0] = 2;
+        let mut chars = base32_encode(&payload);
+        let check = check_value(&payload);
+        for i in (0..CHECK_CHARS).rev() {
+            chars.push(ALPHABET[((check >> (i * 5)) & 0x1F) as usize]);
+        }
+        let other = ["EU-", core::str::from_utf8(&chars).unwrap()].concat();
+        assert_eq!(EnrolledUnit::parse(&other), Err(EuError::UnsupportedVersion(2)));
+    }
+}
@@ -0,0 +1,53 @@
+// Ed25519 verification keys baked in at build time as hex environment
+// variables; a build without one refuses everything signed for that purpose.
+use ed25519_dalek::{Signature, VerifyingKey, SIGNATURE_LENGTH};
+
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Backup tokens (`TUFF_BACKUP_TOKEN.sig`).
+pub const BACKUP_TOKEN_KEY_HEX: Option<&str> = option_env!("TUFF_TOKEN_PUBKEY");
+/// Release manifests.
+pub const RELEASE_KEY_HEX: Option<&str> = option_env!("TUFF_RELEASE_PUBKEY");
+
+pub fn parse_public_key(hex_key: Option<&str>) -> Option<[u8; PUBLIC_KEY_LEN]> {
+    let mut key = [0u8; PUBLIC_KEY_LEN];
+    hex::decode_to_slice(hex_key?.trim(), &mut key).ok()?;
+    Some(key)
+}
+
+/// Why `verify_detached` refused its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetachedError {
+    /// Too short to carry a signature, or a malformed one.
+    Format,
+    /// The public key is not a valid Ed25519 key.
+    BadKey,
+    BadSignature,
+}
+
+/// Checks the Ed25519 signature that closes `data` under `public_key` and
+/// returns the bytes it covers.
+pub fn verify_detached<'a>(
+    data: &'a [u8],
+    public_key: &[u8; PUBLIC_KEY_LEN],
+) -> Result<&'a [u8], DetachedError> {
+    if data.len() < SIGNATURE_LENGTH {
+        return Err(DetachedError::Format);
+    }
+    let (signed, signature) = data.split_at(data.len() - SIGNATURE_LENGTH);
+    let key = VerifyingKey::from_bytes(public_key).map_err(|_| DetachedError::BadKey)?;
+    let signature = Signature::from_slice(signature).map_err(|_| DetachedError::Format)?;
+    key.verify_strict(signed, &signature)
+        .map_err(|_| DetachedError::BadSignature)?;
+    Ok(signed)
+}
+
+/// Appends the signature `verify_detached` expects.
+#[cfg(test)]
+pub(crate) fn sign_detached(mut data: alloc::vec::Vec<u8>, key: &ed25519_dalek::SigningKey) -> alloc::vec::Vec<u8> {
+    use ed25519_dalek::Signer;
+
+    let signature = key.sign(&data);
+    data.extend_from_slice(&signature.to_bytes());
+    data
+}
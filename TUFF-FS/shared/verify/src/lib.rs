@@ -1,5 +1,7 @@
 #![no_std]
 extern crate alloc;
 
+pub mod backup_token;
 pub mod eu_validator;
+pub mod keys;
 pub mod manifest;
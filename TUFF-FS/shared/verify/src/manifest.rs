@@ -1,3 +1,280 @@
+// Signed TUFF-OS release manifest: which kernel, rootfs and EFI binary make
+// up a release, with the size and SHA-256 of each, verified against
+// `TUFF_RELEASE_PUBKEY`. This is the check itself; nothing in the boot path
+// calls it yet.
+//
+// Layout (v1), integers little-endian:
+//   magic "TUFFMAN" | version u8 = 1
+//   release_len u8 | release (UTF-8, e.g. "0.2.0")
+//   created_at i64 (Unix seconds)
+//   artifact_count u8
+//   per artifact: kind u8 | size u64 | sha256 [32]
+//   Ed25519 signature [64] over every preceding byte
 use alloc::string::String;
+use alloc::vec::Vec;
+use sha2::{Digest, Sha256};
 
-pub struct Manifest { pub name: String }
+use crate::keys::{self, DetachedError, PUBLIC_KEY_LEN};
+
+pub const MANIFEST_FILE_NAME: &str = "TUFF_RELEASE.manifest";
+pub const MAGIC: &[u8; 7] = b"TUFFMAN";
+pub const VERSION: u8 = 1;
+const ARTIFACT_LEN: usize = 1 + 8 + 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestError {
+    /// Truncated or malformed manifest.
+    Format,
+    /// Newer manifest format than this build understands.
+    UnsupportedVersion(u8),
+    /// This build carries no (valid) release verification key.
+    NoEmbeddedKey,
+    /// Signature does not verify under the release key.
+    BadSignature,
+    /// An artifact kind is listed twice.
+    DuplicateArtifact(ArtifactKind),
+    /// The release does not list this artifact.
+    MissingArtifact(ArtifactKind),
+    SizeMismatch(ArtifactKind),
+    HashMismatch(ArtifactKind),
+}
+
+impl From<DetachedError> for ManifestError {
+    fn from(e: DetachedError) -> Self {
+        match e {
+            DetachedError::Format => ManifestError::Format,
+            DetachedError::BadKey => ManifestError::NoEmbeddedKey,
+            DetachedError::BadSignature => ManifestError::BadSignature,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtifactKind {
+    Kernel,
+    /// `rootfs.squashfs`
+    RootFs,
+    /// Signed `tuffctl_efi` loader.
+    EfiBinary,
+}
+
+impl ArtifactKind {
+    fn code(self) -> u8 {
+        match self {
+            ArtifactKind::Kernel => 1,
+            ArtifactKind::RootFs => 2,
+            ArtifactKind::EfiBinary => 3,
+        }
+    }
+
+    fn from_code(code: u8) -> Result<Self, ManifestError> {
+        match code {
+            1 => Ok(ArtifactKind::Kernel),
+            2 => Ok(ArtifactKind::RootFs),
+            3 => Ok(ArtifactKind::EfiBinary),
+            _ => Err(ManifestError::Format),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    pub size: u64,
+    pub sha256: [u8; 32],
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    pub release: String,
+    pub created_at: i64,
+    pub artifacts: Vec<Artifact>,
+}
+
+impl Manifest {
+    /// The signed part of the manifest, i.e. everything but the signature.
+    pub fn encode_unsigned(&self) -> Result<Vec<u8>, ManifestError> {
+        let release_len = u8::try_from(self.release.len()).map_err(|_| ManifestError::Format)?;
+        let count = u8::try_from(self.artifacts.len()).map_err(|_| ManifestError::Format)?;
+        let mut out = Vec::with_capacity(MAGIC.len() + 11 + self.release.len() + self.artifacts.len() * ARTIFACT_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.push(release_len);
+        out.extend_from_slice(self.release.as_bytes());
+        out.extend_from_slice(&self.created_at.to_le_bytes());
+        out.push(count);
+        for a in &self.artifacts {
+            out.push(a.kind.code());
+            out.extend_from_slice(&a.size.to_le_bytes());
+            out.extend_from_slice(&a.sha256);
+        }
+        Ok(out)
+    }
+
+    /// Decodes the signed part (without the signature).
+    pub fn decode(data: &[u8]) -> Result<Self, ManifestError> {
+        let rest = data.strip_prefix(MAGIC.as_slice()).ok_or(ManifestError::Format)?;
+        let (&version, rest) = rest.split_first().ok_or(ManifestError::Format)?;
+        if version != VERSION {
+            return Err(ManifestError::UnsupportedVersion(version));
+        }
+        let (&release_len, rest) = rest.split_first().ok_or(ManifestError::Format)?;
+        let release_len = release_len as usize;
+        if rest.len() < release_len + 9 {
+            return Err(ManifestError::Format);
+        }
+        let release = core::str::from_utf8(&rest[..release_len]).map_err(|_| ManifestError::Format)?;
+        let rest = &rest[release_len..];
+        let created_at = i64::from_le_bytes(rest[..8].try_into().unwrap());
+        let count = rest[8] as usize;
+        let entries = &rest[9..];
+        if entries.len() != count * ARTIFACT_LEN {
+            return Err(ManifestError::Format);
+        }
+
+        let mut artifacts: Vec<Artifact> = Vec::with_capacity(count);
+        for entry in entries.chunks(ARTIFACT_LEN) {
+            let kind = ArtifactKind::from_code(entry[0])?;
+            if artifacts.iter().any(|a| a.kind == kind) {
+                return Err(ManifestError::DuplicateArtifact(kind));
+            }
+            artifacts.push(Artifact {
+                kind,
+                size: u64::from_le_bytes(entry[1..9].try_into().unwrap()),
+                sha256: entry[9..].try_into().unwrap(),
+            });
+        }
+        Ok(Manifest { release: String::from(release), created_at, artifacts })
+    }
+
+    pub fn artifact(&self, kind: ArtifactKind) -> Result<&Artifact, ManifestError> {
+        self.artifacts.iter().find(|a| a.kind == kind).ok_or(ManifestError::MissingArtifact(kind))
+    }
+
+    /// Checks an artifact held entirely in memory.
+    pub fn check_artifact(&self, kind: ArtifactKind, data: &[u8]) -> Result<(), ManifestError> {
+        let mut check = ArtifactCheck::new(self.artifact(kind)?);
+        check.update(data);
+        check.finish()
+    }
+}
+
+/// Incremental artifact check, for artifacts read in pieces (e.g. through
+/// UEFI file protocol reads or a streamed rootfs).
+pub struct ArtifactCheck<'a> {
+    expected: &'a Artifact,
+    hasher: Sha256,
+    seen: u64,
+}
+
+impl<'a> ArtifactCheck<'a> {
+    pub fn new(expected: &'a Artifact) -> Self {
+        Self { expected, hasher: Sha256::new(), seen: 0 }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+        self.seen += data.len() as u64;
+    }
+
+    pub fn finish(self) -> Result<(), ManifestError> {
+        if self.seen != self.expected.size {
+            return Err(ManifestError::SizeMismatch(self.expected.kind));
+        }
+        if self.hasher.finalize().as_slice() != self.expected.sha256 {
+            return Err(ManifestError::HashMismatch(self.expected.kind));
+        }
+        Ok(())
+    }
+}
+
+/// Checks the signature over a manifest file and decodes it.
+pub fn verify(data: &[u8], public_key: &[u8; PUBLIC_KEY_LEN]) -> Result<Manifest, ManifestError> {
+    let signed = keys::verify_detached(data, public_key)?;
+    Manifest::decode(signed)
+}
+
+/// The release verification key embedded in this build.
+pub fn embedded_public_key() -> Result<[u8; PUBLIC_KEY_LEN], ManifestError> {
+    keys::parse_public_key(keys::RELEASE_KEY_HEX).ok_or(ManifestError::NoEmbeddedKey)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ed25519_dalek::SigningKey;
+
+    const KERNEL: &[u8] = b"bzImage contents";
+    const ROOTFS: &[u8] = b"hsqs squashfs contents";
+
+    fn release() -> Manifest {
+        Manifest {
+            release: String::from("0.2.0"),
+            created_at: 1_750_000_000,
+            artifacts: alloc::vec![
+                Artifact { kind: ArtifactKind::Kernel, size: KERNEL.len() as u64, sha256: Sha256::digest(KERNEL).into() },
+                Artifact { kind: ArtifactKind::RootFs, size: ROOTFS.len() as u64, sha256: Sha256::digest(ROOTFS).into() },
+            ],
+        }
+    }
+
+    fn signed(manifest: &Manifest, key: &SigningKey) -> Vec<u8> {
+        keys::sign_detached(manifest.encode_unsigned().unwrap(), key)
+    }
+
+    #[test]
+    fn signed_manifest_round_trips_and_checks_artifacts() {
+        let key = SigningKey::from_bytes(&[4u8; 32]);
+        let data = signed(&release(), &key);
+        let manifest = verify(&data, &key.verifying_key().to_bytes()).unwrap();
+        assert_eq!(manifest, release());
+
+        manifest.check_artifact(ArtifactKind::Kernel, KERNEL).unwrap();
+        let mut streamed = ArtifactCheck::new(manifest.artifact(ArtifactKind::RootFs).unwrap());
+        for piece in ROOTFS.chunks(5) {
+            streamed.update(piece);
+        }
+        streamed.finish().unwrap();
+
+        assert_eq!(
+            manifest.check_artifact(ArtifactKind::Kernel, b"bzImage content!"),
+            Err(ManifestError::HashMismatch(ArtifactKind::Kernel))
+        );
+        assert_eq!(
+            manifest.check_artifact(ArtifactKind::Kernel, &KERNEL[1..]),
+            Err(ManifestError::SizeMismatch(ArtifactKind::Kernel))
+        );
+        assert_eq!(
+            manifest.check_artifact(ArtifactKind::EfiBinary, b""),
+            Err(ManifestError::MissingArtifact(ArtifactKind::EfiBinary))
+        );
+    }
+
+    #[test]
+    fn tampering_and_malformed_manifests_are_rejected() {
+        let key = SigningKey::from_bytes(&[4u8; 32]);
+        let public = key.verifying_key().to_bytes();
+        let data = signed(&release(), &key);
+
+        let mut tampered = data.clone();
+        // First byte of the kernel hash.
+        tampered[MAGIC.len() + 2 + 5 + 8 + 1 + 9] ^= 1;
+        assert_eq!(verify(&tampered, &public), Err(ManifestError::BadSignature));
+
+        let other = SigningKey::from_bytes(&[5u8; 32]).verifying_key().to_bytes();
+        assert_eq!(verify(&data, &other), Err(ManifestError::BadSignature));
+
+        let mut twice = release();
+        twice.artifacts.push(twice.artifacts[0].clone());
+        assert_eq!(
+            verify(&signed(&twice, &key), &public),
+            Err(ManifestError::DuplicateArtifact(ArtifactKind::Kernel))
+        );
+
+        let unsigned = release().encode_unsigned().unwrap();
+        assert_eq!(Manifest::decode(&unsigned[..unsigned.len() - 1]), Err(ManifestError::Format));
+        let mut newer = unsigned;
+        newer[MAGIC.len()] = 2;
+        assert_eq!(Manifest::decode(&newer), Err(ManifestError::UnsupportedVersion(2)));
+    }
+}
@@ -0,0 +1,17 @@
+#!/bin/bash
+set -e
+
+KEY_DIR="keys/release"
+mkdir -p $KEY_DIR
+
+if [ -f "$KEY_DIR/release.key" ]; then
+    echo "[INFO] Release key already exists in $KEY_DIR. Skipping generation."
+else
+    echo "[INFO] Generating Ed25519 release signing key..."
+    openssl genpkey -algorithm ed25519 -out $KEY_DIR/release.key
+fi
+
+# Raw 32-byte public key, hex encoded, for TUFF_RELEASE_PUBKEY at build time.
+openssl pkey -in $KEY_DIR/release.key -pubout -outform DER | tail -c 32 | od -An -tx1 | tr -d ' \n' > $KEY_DIR/release.pub.hex
+
+echo "[SUCCESS] Build with: TUFF_RELEASE_PUBKEY=$(cat $KEY_DIR/release.pub.hex)"
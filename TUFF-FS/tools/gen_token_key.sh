@@ -0,0 +1,17 @@
+#!/bin/bash
+set -e
+
+KEY_DIR="keys/backup_token"
+mkdir -p $KEY_DIR
+
+if [ -f "$KEY_DIR/token.key" ]; then
+    echo "[INFO] Token key already exists in $KEY_DIR. Skipping generation."
+else
+    echo "[INFO] Generating Ed25519 backup token key..."
+    openssl genpkey -algorithm ed25519 -out $KEY_DIR/token.key
+fi
+
+# Raw 32-byte public key, hex encoded, for TUFF_TOKEN_PUBKEY at build time.
+openssl pkey -in $KEY_DIR/token.key -pubout -outform DER | tail -c 32 | od -An -tx1 | tr -d ' \n' > $KEY_DIR/token.pub.hex
+
+echo "[SUCCESS] Build with: TUFF_TOKEN_PUBKEY=$(cat $KEY_DIR/token.pub.hex)"
@@ -0,0 +1,64 @@
+#!/bin/bash
+# Issues TUFF_BACKUP_TOKEN.sig (format: shared/verify/src/backup_token.rs).
+set -e
+
+SERIAL=$1
+EXPIRES=$2
+OPS=$3
+OUTPUT=${4:-TUFF_BACKUP_TOKEN.sig}
+KEY="keys/backup_token/token.key"
+
+if [ -z "$SERIAL" ] || [ -z "$EXPIRES" ] || [ -z "$OPS" ]; then
+    echo "Usage: $0 <disk_serial> <expiry date|unix seconds> <install,backup,restore> [output]"
+    exit 1
+fi
+
+if [ ! -f "$KEY" ]; then
+    echo "[ERROR] Token key not found. Run tools/gen_token_key.sh first."
+    exit 1
+fi
+
+if [[ "$EXPIRES" =~ ^[0-9]+$ ]]; then
+    EXPIRES_AT=$EXPIRES
+else
+    EXPIRES_AT=$(date -d "$EXPIRES" +%s)
+fi
+
+MASK=0
+for op in ${OPS//,/ }; do
+    case $op in
+        install) MASK=$((MASK | 1)) ;;
+        backup)  MASK=$((MASK | 2)) ;;
+        restore) MASK=$((MASK | 4)) ;;
+        *) echo "[ERROR] Unknown operation: $op"; exit 1 ;;
+    esac
+done
+
+SERIAL_LEN=$(printf %s "$SERIAL" | wc -c)
+if [ "$SERIAL_LEN" -gt 255 ]; then
+    echo "[ERROR] Disk serial is longer than 255 bytes."
+    exit 1
+fi
+
+# Writes $1 as $2 little-endian bytes.
+le() {
+    local value=$1 i
+    for ((i = 0; i < $2; i++)); do
+        printf "\\x$(printf %02x $(((value >> (8 * i)) & 0xff)))"
+    done
+}
+
+BODY=$(mktemp)
+trap 'rm -f "$BODY"' EXIT
+{
+    printf 'TUFFBTK\x01'
+    le "$EXPIRES_AT" 8
+    le "$MASK" 4
+    le "$SERIAL_LEN" 1
+    printf '%s' "$SERIAL"
+} > "$BODY"
+
+cp "$BODY" "$OUTPUT"
+openssl pkeyutl -sign -inkey "$KEY" -rawin -in "$BODY" >> "$OUTPUT"
+
+echo "[SUCCESS] Token for $SERIAL (ops=$OPS, expires $(date -d @$EXPIRES_AT -u +%FT%TZ)) written to $OUTPUT"
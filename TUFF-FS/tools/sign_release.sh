@@ -0,0 +1,59 @@
+#!/bin/bash
+# Writes the signed release manifest (format: shared/verify/src/manifest.rs).
+set -e
+
+RELEASE=$1
+KERNEL=$2
+ROOTFS=$3
+EFI=$4
+OUTPUT=${5:-TUFF_RELEASE.manifest}
+KEY="keys/release/release.key"
+
+if [ -z "$RELEASE" ] || [ -z "$KERNEL" ] || [ -z "$ROOTFS" ] || [ -z "$EFI" ]; then
+    echo "Usage: $0 <release> <kernel> <rootfs.squashfs> <bootx64.efi> [output]"
+    exit 1
+fi
+
+if [ ! -f "$KEY" ]; then
+    echo "[ERROR] Release key not found. Run tools/gen_release_key.sh first."
+    exit 1
+fi
+
+RELEASE_LEN=$(printf %s "$RELEASE" | wc -c)
+if [ "$RELEASE_LEN" -gt 255 ]; then
+    echo "[ERROR] Release name is longer than 255 bytes."
+    exit 1
+fi
+
+# Writes $1 as $2 little-endian bytes.
+le() {
+    local value=$1 i
+    for ((i = 0; i < $2; i++)); do
+        printf "\\x$(printf %02x $(((value >> (8 * i)) & 0xff)))"
+    done
+}
+
+# kind code, file
+artifact() {
+    le "$1" 1
+    le "$(stat -c %s "$2")" 8
+    sha256sum "$2" | cut -d' ' -f1 | xxd -r -p
+}
+
+BODY=$(mktemp)
+trap 'rm -f "$BODY"' EXIT
+{
+    printf 'TUFFMAN\x01'
+    le "$RELEASE_LEN" 1
+    printf '%s' "$RELEASE"
+    le "$(date +%s)" 8
+    le 3 1
+    artifact 1 "$KERNEL"
+    artifact 2 "$ROOTFS"
+    artifact 3 "$EFI"
+} > "$BODY"
+
+cp "$BODY" "$OUTPUT"
+openssl pkeyutl -sign -inkey "$KEY" -rawin -in "$BODY" >> "$OUTPUT"
+
+echo "[SUCCESS] Release $RELEASE manifest written to $OUTPUT"
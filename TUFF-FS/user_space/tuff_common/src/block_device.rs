@@ -0,0 +1,244 @@
+use anyhow::{Context, Result};
+
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+/// Minimum I/O unit for every TUFF-FS device (README: 4096-byte granularity).
+pub const CHUNK_SIZE: usize = 4096;
+
+/// Chunk-addressed access to a TUFF-FS member disk.
+///
+/// All transfers are exactly one chunk and chunk indices are counted from LBA 0.
+pub trait BlockDevice: Send + Sync {
+    fn read_chunk(&self, index: u64, buf: &mut [u8]) -> Result<()>;
+    fn write_chunk(&self, index: u64, buf: &[u8]) -> Result<()>;
+    fn flush(&self) -> Result<()>;
+    /// Device size in bytes (always a multiple of `CHUNK_SIZE`).
+    fn size(&self) -> u64;
+    fn path(&self) -> &Path;
+
+    fn chunk_count(&self) -> u64 {
+        self.size() / CHUNK_SIZE as u64
+    }
+}
+
+/// Image-file backed device, used for loopback images and tests.
+pub struct FileBlockDevice {
+    file: File,
+    path: PathBuf,
+    size: u64,
+}
+
+impl FileBlockDevice {
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open {}", path.display()))?;
+        let size = device_size(&mut file, path)?;
+        Ok(Self { file, path: path.to_path_buf(), size })
+    }
+
+    /// Creates (or truncates) an image file of `chunks` zeroed chunks.
+    pub fn create(path: &Path, chunks: u64) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .with_context(|| format!("Failed to create {}", path.display()))?;
+        let size = chunks * CHUNK_SIZE as u64;
+        file.set_len(size)
+            .with_context(|| format!("Failed to size {}", path.display()))?;
+        Ok(Self { file, path: path.to_path_buf(), size })
+    }
+}
+
+impl BlockDevice for FileBlockDevice {
+    fn read_chunk(&self, index: u64, buf: &mut [u8]) -> Result<()> {
+        check_request(self, index, buf.len())?;
+        self.file
+            .read_exact_at(buf, index * CHUNK_SIZE as u64)
+            .with_context(|| format!("Read of chunk {} on {} failed", index, self.path.display()))
+    }
+
+    fn write_chunk(&self, index: u64, buf: &[u8]) -> Result<()> {
+        check_request(self, index, buf.len())?;
+        self.file
+            .write_all_at(buf, index * CHUNK_SIZE as u64)
+            .with_context(|| format!("Write of chunk {} on {} failed", index, self.path.display()))
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.file
+            .sync_data()
+            .with_context(|| format!("fsync of {} failed", self.path.display()))
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// `/dev/sdX`-style device opened with O_DIRECT so writes bypass the page cache.
+pub struct RawBlockDevice {
+    file: File,
+    path: PathBuf,
+    size: u64,
+}
+
+// O_DIRECT requires the user buffer to be aligned to the logical block size.
+#[repr(C, align(4096))]
+struct AlignedChunk([u8; CHUNK_SIZE]);
+
+impl RawBlockDevice {
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with(path, true)
+    }
+
+    pub fn open_readonly(path: &Path) -> Result<Self> {
+        Self::open_with(path, false)
+    }
+
+    fn open_with(path: &Path, writable: bool) -> Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .custom_flags(libc::O_DIRECT)
+            .open(path)
+            .with_context(|| format!("Failed to open {} with O_DIRECT", path.display()))?;
+        let size = device_size(&mut file, path)?;
+        Ok(Self { file, path: path.to_path_buf(), size })
+    }
+}
+
+impl BlockDevice for RawBlockDevice {
+    fn read_chunk(&self, index: u64, buf: &mut [u8]) -> Result<()> {
+        check_request(self, index, buf.len())?;
+        let mut aligned = Box::new(AlignedChunk([0u8; CHUNK_SIZE]));
+        self.file
+            .read_exact_at(&mut aligned.0, index * CHUNK_SIZE as u64)
+            .with_context(|| format!("Read of chunk {} on {} failed", index, self.path.display()))?;
+        buf.copy_from_slice(&aligned.0);
+        Ok(())
+    }
+
+    fn write_chunk(&self, index: u64, buf: &[u8]) -> Result<()> {
+        check_request(self, index, buf.len())?;
+        let mut aligned = Box::new(AlignedChunk([0u8; CHUNK_SIZE]));
+        aligned.0.copy_from_slice(buf);
+        self.file
+            .write_all_at(&aligned.0, index * CHUNK_SIZE as u64)
+            .with_context(|| format!("Write of chunk {} on {} failed", index, self.path.display()))
+    }
+
+    fn flush(&self) -> Result<()> {
+        // O_DIRECT skips the page cache but not the drive's write cache.
+        self.file
+            .sync_all()
+            .with_context(|| format!("fsync of {} failed", self.path.display()))
+    }
+
+    fn size(&self) -> u64 {
+        self.size
+    }
+
+    fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Opens `path` as a raw device if it is a block special file, otherwise as an image file.
+pub fn open_device(path: &Path) -> Result<Box<dyn BlockDevice>> {
+    use std::os::unix::fs::FileTypeExt;
+
+    let meta = std::fs::metadata(path)
+        .with_context(|| format!("Failed to stat {}", path.display()))?;
+    if meta.file_type().is_block_device() {
+        Ok(Box::new(RawBlockDevice::open(path)?))
+    } else {
+        Ok(Box::new(FileBlockDevice::open(path)?))
+    }
+}
+
+fn device_size(file: &mut File, path: &Path) -> Result<u64> {
+    // SEEK_END works for both regular files and block devices.
+    let size = file
+        .seek(SeekFrom::End(0))
+        .with_context(|| format!("Failed to determine size of {}", path.display()))?;
+    if size % CHUNK_SIZE as u64 != 0 {
+        anyhow::bail!(
+            "{} size {} is not a multiple of {} bytes",
+            path.display(),
+            size,
+            CHUNK_SIZE
+        );
+    }
+    Ok(size)
+}
+
+fn check_request<D: BlockDevice + ?Sized>(dev: &D, index: u64, len: usize) -> Result<()> {
+    if len != CHUNK_SIZE {
+        anyhow::bail!("I/O buffer must be {} bytes, got {}", CHUNK_SIZE, len);
+    }
+    if index >= dev.chunk_count() {
+        anyhow::bail!(
+            "chunk {} out of range for {} ({} chunks)",
+            index,
+            dev.path().display(),
+            dev.chunk_count()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockDevice, FileBlockDevice, CHUNK_SIZE};
+
+    fn temp_image(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("tuff_bd_{}_{}.img", name, std::process::id()))
+    }
+
+    #[test]
+    fn file_device_roundtrip() {
+        let path = temp_image("roundtrip");
+        let dev = FileBlockDevice::create(&path, 4).unwrap();
+        assert_eq!(dev.chunk_count(), 4);
+
+        let data = [0xA5u8; CHUNK_SIZE];
+        dev.write_chunk(3, &data).unwrap();
+        dev.flush().unwrap();
+
+        let reopened = FileBlockDevice::open(&path).unwrap();
+        let mut buf = [0u8; CHUNK_SIZE];
+        reopened.read_chunk(3, &mut buf).unwrap();
+        assert_eq!(buf, data);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_unaligned_and_out_of_range_io() {
+        let path = temp_image("bounds");
+        let dev = FileBlockDevice::create(&path, 2).unwrap();
+        assert!(dev.write_chunk(0, &[0u8; 512]).is_err());
+        assert!(dev.write_chunk(2, &[0u8; CHUNK_SIZE]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn rejects_image_with_partial_chunk() {
+        let path = temp_image("partial");
+        std::fs::write(&path, vec![0u8; CHUNK_SIZE + 1]).unwrap();
+        assert!(FileBlockDevice::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,221 @@
+// Control protocol between tuffctl and tuffd over a Unix-domain socket.
+//
+// One request and one response per connection, each framed as
+// [u32 LE length][JSON body].
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use zeroize::Zeroizing;
+
+use crate::data_chunk::ChunkAddr;
+use crate::paths::CONTROL_SOCKET_PATH;
+use crate::state::State;
+
+/// Upper bound on a frame body; anything larger is treated as a protocol error.
+pub const MAX_FRAME_LEN: usize = 1024 * 1024;
+/// Upper bound on key material carried in a request.
+pub const MAX_SECRET_LEN: usize = 64;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Request {
+    /// Report state, key presence, the open index and attached volumes.
+    Status,
+    /// Write a file through the write queue; the next commit publishes it.
+    Put { name: String, data: Vec<u8> },
+    /// Flush pending writes and publish the next IndexChunk generation.
+    Commit,
+    /// Leave Freeze after re-verifying the Master Key and the index chain.
+    /// The key travels only over the root-only socket.
+    Unfreeze {
+        #[serde(with = "secret_bytes")]
+        master_key: Zeroizing<Vec<u8>>,
+        reason: String,
+    },
+    /// Drop generations outside the retention window and free their chunks.
+    Truncate { keep: Option<usize>, max_age_secs: Option<i64>, dry_run: bool },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "result", rename_all = "snake_case")]
+pub enum Response {
+    Status {
+        version: String,
+        state: State,
+        key_loaded: bool,
+        index: Option<IndexInfo>,
+        volumes: Vec<VolumeInfo>,
+    },
+    Unfrozen { volume_uuid: String, generation: u8 },
+    Staged { name: String, size: u64, queue_depth: usize },
+    Committed { generation: u8, timestamp: i64, entries: usize },
+    Truncated { retained: Vec<u8>, dropped: Vec<u8>, freed: Vec<ChunkAddr>, dry_run: bool },
+    Error { message: String },
+}
+
+/// The IndexChunk generation tuffd is currently serving.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IndexInfo {
+    pub volume_uuid: String,
+    pub generation: u8,
+    pub timestamp: i64,
+    pub replicas: Vec<u64>,
+}
+
+/// A volume whose member disks were found by the last probe.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VolumeInfo {
+    pub volume_uuid: String,
+    pub members: Vec<MemberInfo>,
+    pub open: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MemberInfo {
+    pub device: String,
+    pub hw_id: u64,
+}
+
+/// Frames may carry the Master Key: the body is sized before it is written so
+/// the buffer never reallocates, and it is wiped when dropped.
+pub fn encode_frame<T: Serialize>(msg: &T) -> Result<Zeroizing<Vec<u8>>> {
+    let mut counter = ByteCount(0);
+    serde_json::to_writer(&mut counter, msg)?;
+    let len = counter.0;
+    if len > MAX_FRAME_LEN {
+        anyhow::bail!("control frame of {} bytes exceeds {}", len, MAX_FRAME_LEN);
+    }
+    let mut frame = Zeroizing::new(Vec::with_capacity(4 + len));
+    frame.extend_from_slice(&(len as u32).to_le_bytes());
+    serde_json::to_writer(&mut *frame, msg)?;
+    Ok(frame)
+}
+
+struct ByteCount(usize);
+
+impl Write for ByteCount {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Validates a frame header and returns the body length that follows it.
+pub fn frame_len(header: [u8; 4]) -> Result<usize> {
+    let len = u32::from_le_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        anyhow::bail!("control frame of {} bytes exceeds {}", len, MAX_FRAME_LEN);
+    }
+    Ok(len)
+}
+
+pub fn decode_body<T: DeserializeOwned>(body: &[u8]) -> Result<T> {
+    serde_json::from_slice(body).context("malformed control message")
+}
+
+pub fn write_frame<W: Write, T: Serialize>(w: &mut W, msg: &T) -> Result<()> {
+    w.write_all(&encode_frame(msg)?)?;
+    w.flush()?;
+    Ok(())
+}
+
+pub fn read_frame<R: Read, T: DeserializeOwned>(r: &mut R) -> Result<T> {
+    let mut header = [0u8; 4];
+    r.read_exact(&mut header)?;
+    let mut body = Zeroizing::new(vec![0u8; frame_len(header)?]);
+    r.read_exact(&mut body)?;
+    decode_body(&body)
+}
+
+/// Sends `request` to the running tuffd and waits for its answer.
+pub fn call(request: &Request) -> Result<Response> {
+    let socket = Path::new(CONTROL_SOCKET_PATH);
+    let mut stream = UnixStream::connect(socket)
+        .with_context(|| format!("Cannot reach tuffd at {} (is it running?)", socket.display()))?;
+    write_frame(&mut stream, request)?;
+    read_frame(&mut stream).context("No valid response from tuffd")
+}
+
+/// Key bytes as a JSON array, read into a buffer that is reserved once and
+/// wiped on drop.
+mod secret_bytes {
+    use super::MAX_SECRET_LEN;
+    use serde::de::{self, SeqAccess, Visitor};
+    use serde::{Deserializer, Serialize, Serializer};
+    use std::fmt;
+    use zeroize::Zeroizing;
+
+    pub fn serialize<S: Serializer>(bytes: &Zeroizing<Vec<u8>>, s: S) -> Result<S::Ok, S::Error> {
+        bytes.as_slice().serialize(s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Zeroizing<Vec<u8>>, D::Error> {
+        d.deserialize_seq(SecretVisitor)
+    }
+
+    struct SecretVisitor;
+
+    impl<'de> Visitor<'de> for SecretVisitor {
+        type Value = Zeroizing<Vec<u8>>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "at most {} bytes of key material", MAX_SECRET_LEN)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut out = Zeroizing::new(Vec::with_capacity(MAX_SECRET_LEN));
+            while let Some(byte) = seq.next_element::<u8>()? {
+                if out.len() == MAX_SECRET_LEN {
+                    return Err(de::Error::invalid_length(MAX_SECRET_LEN + 1, &self));
+                }
+                out.push(byte);
+            }
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn frame_roundtrip_and_limits() {
+        let response = Response::Committed { generation: 7, timestamp: 1_700_000_000, entries: 3 };
+        let frame = encode_frame(&response).unwrap();
+        let back: Response = read_frame(&mut Cursor::new(&frame)).unwrap();
+        assert_eq!(back, response);
+
+        let mut oversized = Cursor::new(((MAX_FRAME_LEN + 1) as u32).to_le_bytes().to_vec());
+        assert!(read_frame::<_, Request>(&mut oversized).is_err());
+
+        let frame = encode_frame(&Request::Status).unwrap();
+        assert_eq!(read_frame::<_, Request>(&mut Cursor::new(&frame)).unwrap(), Request::Status);
+
+        let truncated = &frame[..frame.len() - 1];
+        assert!(read_frame::<_, Request>(&mut Cursor::new(truncated)).is_err());
+    }
+
+    #[test]
+    fn unfreeze_key_roundtrip_and_limit() {
+        let request = Request::Unfreeze { master_key: Zeroizing::new(vec![7u8; 32]), reason: "test".into() };
+        let frame = encode_frame(&request).unwrap();
+        assert_eq!(frame.len(), frame.capacity());
+        assert_eq!(read_frame::<_, Request>(&mut Cursor::new(&*frame)).unwrap(), request);
+
+        let oversized = Request::Unfreeze {
+            master_key: Zeroizing::new(vec![7u8; MAX_SECRET_LEN + 1]),
+            reason: "test".into(),
+        };
+        let frame = encode_frame(&oversized).unwrap();
+        assert!(read_frame::<_, Request>(&mut Cursor::new(&*frame)).is_err());
+    }
+}
@@ -0,0 +1,342 @@
+use anyhow::{Context, Result};
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use tuff_crypto::chunk_cipher::{self, NONCE_LEN};
+use tuff_schemas::tuff::tuff_os::DataChunkHeader;
+
+use crate::block_device::{BlockDevice, CHUNK_SIZE};
+use crate::error::ChainError;
+use crate::layout::DATA_START;
+
+pub const DATA_CHUNK_HEADER_LEN: usize = std::mem::size_of::<DataChunkHeader>();
+/// Set on every chunk written by `write_chain`; blank or foreign chunks lack it.
+pub const DATA_FLAG_VALID: u8 = 0x01;
+
+/// Chunk encryption bound to the on-disk address of each chunk.
+///
+/// The sealed form always fills exactly `CHUNK_SIZE` bytes; `overhead` of them
+/// carry the cipher's framing (nonce, tag), the rest is the plaintext chunk.
+pub trait ChunkCipher {
+    fn overhead(&self) -> usize;
+    /// `plain` is `CHUNK_SIZE - overhead()` bytes, `sealed` is `CHUNK_SIZE` bytes.
+    fn seal(&self, hw_id: u64, chunk_id: u64, plain: &[u8], sealed: &mut [u8]) -> Result<()>;
+    /// Must fail if `sealed` was not produced by `seal` for the same address.
+    fn open(&self, hw_id: u64, chunk_id: u64, sealed: &[u8], plain: &mut [u8]) -> Result<()>;
+}
+
+/// AES-256-GCM from `tuff_crypto`; a fresh nonce is drawn from the OS RNG per write.
+impl ChunkCipher for chunk_cipher::ChunkCipher {
+    fn overhead(&self) -> usize {
+        chunk_cipher::OVERHEAD
+    }
+
+    fn seal(&self, hw_id: u64, chunk_id: u64, plain: &[u8], sealed: &mut [u8]) -> Result<()> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::rngs::OsRng.fill_bytes(&mut nonce);
+        chunk_cipher::ChunkCipher::seal(self, hw_id, chunk_id, &nonce, plain, sealed)
+            .map_err(|e| anyhow::anyhow!("chunk seal failed: {:?}", e))
+    }
+
+    fn open(&self, hw_id: u64, chunk_id: u64, sealed: &[u8], plain: &mut [u8]) -> Result<()> {
+        chunk_cipher::ChunkCipher::open(self, hw_id, chunk_id, sealed, plain)
+            .map_err(|e| anyhow::anyhow!("chunk open failed: {:?}", e))
+    }
+}
+
+/// Physical location of a data chunk: member disk and chunk index on it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct ChunkAddr {
+    pub hw_id: u64,
+    pub chunk_id: u64,
+}
+
+impl ChunkAddr {
+    /// `hw_id` 0 never names a disk, so it terminates a chain.
+    pub const END: ChunkAddr = ChunkAddr { hw_id: 0, chunk_id: 0 };
+
+    pub fn is_end(&self) -> bool {
+        self.hw_id == 0
+    }
+}
+
+/// Member disks of one volume keyed by `hw_id`.
+pub type MemberMap = BTreeMap<u64, Box<dyn BlockDevice>>;
+
+pub fn payload_capacity(cipher: &dyn ChunkCipher) -> usize {
+    CHUNK_SIZE - cipher.overhead() - DATA_CHUNK_HEADER_LEN
+}
+
+pub fn chunks_needed(len: u64, cipher: &dyn ChunkCipher) -> u64 {
+    len.div_ceil(payload_capacity(cipher) as u64)
+}
+
+/// Splits `data` over `addrs` (in order) and links the chunks into one chain.
+/// The caller allocates exactly `chunks_needed` addresses; `addrs[0]` becomes the
+/// `FileEntry` start.
+pub fn write_chain(
+    members: &MemberMap,
+    cipher: &dyn ChunkCipher,
+    addrs: &[ChunkAddr],
+    data: &[u8],
+) -> Result<()> {
+    for (addr, sealed) in seal_chain(members, cipher, addrs, data)? {
+        members[&addr.hw_id].write_chunk(addr.chunk_id, &sealed)?;
+    }
+    Ok(())
+}
+
+/// Seals the chain `write_chain` would write, without touching the disks.
+pub fn seal_chain(
+    members: &MemberMap,
+    cipher: &dyn ChunkCipher,
+    addrs: &[ChunkAddr],
+    data: &[u8],
+) -> Result<Vec<(ChunkAddr, Vec<u8>)>> {
+    let capacity = payload_capacity(cipher);
+    let needed = chunks_needed(data.len() as u64, cipher);
+    if addrs.len() as u64 != needed {
+        anyhow::bail!("{} bytes need {} chunks, got {} addresses", data.len(), needed, addrs.len());
+    }
+    let unique: HashSet<&ChunkAddr> = addrs.iter().collect();
+    if unique.len() != addrs.len() {
+        anyhow::bail!("chunk addresses must be distinct");
+    }
+
+    let mut plain = vec![0u8; CHUNK_SIZE - cipher.overhead()];
+    let mut chain = Vec::with_capacity(addrs.len());
+    for (i, (addr, part)) in addrs.iter().zip(data.chunks(capacity)).enumerate() {
+        let dev = members
+            .get(&addr.hw_id)
+            .with_context(|| format!("no member disk with hw_id {:#x}", addr.hw_id))?;
+        if addr.chunk_id < DATA_START || addr.chunk_id >= dev.chunk_count() {
+            anyhow::bail!("chunk {} is outside the data area of {:#x}", addr.chunk_id, addr.hw_id);
+        }
+        let next = addrs.get(i + 1).copied().unwrap_or(ChunkAddr::END);
+        let header = DataChunkHeader::new(DATA_FLAG_VALID, part.len() as u16, next.hw_id, next.chunk_id);
+
+        plain.fill(0);
+        plain[..DATA_CHUNK_HEADER_LEN].copy_from_slice(&header.0);
+        plain[DATA_CHUNK_HEADER_LEN..DATA_CHUNK_HEADER_LEN + part.len()].copy_from_slice(part);
+        let mut sealed = vec![0u8; CHUNK_SIZE];
+        cipher.seal(addr.hw_id, addr.chunk_id, &plain, &mut sealed)?;
+        chain.push((*addr, sealed));
+    }
+    Ok(chain)
+}
+
+/// Lists the addresses of a chain without reassembling the payload.
+pub fn chain_addrs(
+    members: &MemberMap,
+    cipher: &dyn ChunkCipher,
+    start: ChunkAddr,
+) -> Result<Vec<ChunkAddr>> {
+    let mut addrs = Vec::new();
+    walk_chain(members, cipher, start, |addr, _| {
+        addrs.push(addr);
+        Ok(())
+    })?;
+    Ok(addrs)
+}
+
+/// Chunks' worth of payload reserved up front by `read_chain`; beyond that the
+/// buffer grows with what the chain actually holds.
+const READ_RESERVE_CHUNKS: u64 = 256;
+
+/// Follows the chain from `start`, decrypting and reassembling `size` bytes.
+///
+/// `size` comes from an index entry and is only trusted once the chain matches it.
+pub fn read_chain(
+    members: &MemberMap,
+    cipher: &dyn ChunkCipher,
+    start: ChunkAddr,
+    size: u64,
+) -> Result<Vec<u8>> {
+    let reserve = size.min(READ_RESERVE_CHUNKS * payload_capacity(cipher) as u64);
+    let mut out = Vec::with_capacity(reserve as usize);
+    walk_chain(members, cipher, start, |_, payload| {
+        if (out.len() + payload.len()) as u64 > size {
+            return Err(ChainError::SizeMismatch {
+                expected: size,
+                actual: (out.len() + payload.len()) as u64,
+            }
+            .into());
+        }
+        out.extend_from_slice(payload);
+        Ok(())
+    })?;
+    if out.len() as u64 != size {
+        return Err(ChainError::SizeMismatch { expected: size, actual: out.len() as u64 }.into());
+    }
+    Ok(out)
+}
+
+fn walk_chain<F>(members: &MemberMap, cipher: &dyn ChunkCipher, start: ChunkAddr, mut visit: F) -> Result<()>
+where
+    F: FnMut(ChunkAddr, &[u8]) -> Result<()>,
+{
+    let capacity = payload_capacity(cipher);
+    let mut plain = vec![0u8; CHUNK_SIZE - cipher.overhead()];
+    let mut sealed = vec![0u8; CHUNK_SIZE];
+    let mut visited = HashSet::new();
+    let mut cur = start;
+
+    while !cur.is_end() {
+        let dangling = |reason: &str| ChainError::Dangling {
+            hw_id: cur.hw_id,
+            chunk_id: cur.chunk_id,
+            reason: reason.to_string(),
+        };
+        if !visited.insert(cur) {
+            return Err(ChainError::Cycle { hw_id: cur.hw_id, chunk_id: cur.chunk_id }.into());
+        }
+        let dev = members.get(&cur.hw_id).ok_or_else(|| dangling("unknown hw_id"))?;
+        if cur.chunk_id < DATA_START || cur.chunk_id >= dev.chunk_count() {
+            return Err(dangling("outside the data area").into());
+        }
+        dev.read_chunk(cur.chunk_id, &mut sealed)?;
+        cipher
+            .open(cur.hw_id, cur.chunk_id, &sealed, &mut plain)
+            .map_err(|e| dangling(&format!("decryption failed: {}", e)))?;
+
+        let mut raw = [0u8; DATA_CHUNK_HEADER_LEN];
+        raw.copy_from_slice(&plain[..DATA_CHUNK_HEADER_LEN]);
+        let header = DataChunkHeader(raw);
+        if header.flags() & DATA_FLAG_VALID == 0 {
+            return Err(dangling("chunk is not in use").into());
+        }
+        let len = header.payload_len() as usize;
+        if len > capacity {
+            return Err(dangling(&format!("corrupt payload_len {}", len)).into());
+        }
+        visit(cur, &plain[DATA_CHUNK_HEADER_LEN..DATA_CHUNK_HEADER_LEN + len])?;
+
+        cur = ChunkAddr { hw_id: header.next_hw_id(), chunk_id: header.next_chunk_id() };
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device::FileBlockDevice;
+    use crate::layout::min_device_chunks;
+
+    /// Address-bound XOR "cipher": enough to exercise framing and tamper detection.
+    struct TestCipher;
+
+    impl ChunkCipher for TestCipher {
+        fn overhead(&self) -> usize {
+            16
+        }
+        fn seal(&self, hw_id: u64, chunk_id: u64, plain: &[u8], sealed: &mut [u8]) -> Result<()> {
+            sealed[..8].copy_from_slice(&hw_id.to_le_bytes());
+            sealed[8..16].copy_from_slice(&chunk_id.to_le_bytes());
+            for (s, p) in sealed[16..].iter_mut().zip(plain) {
+                *s = p ^ 0x5A;
+            }
+            Ok(())
+        }
+        fn open(&self, hw_id: u64, chunk_id: u64, sealed: &[u8], plain: &mut [u8]) -> Result<()> {
+            if sealed[..8] != hw_id.to_le_bytes() || sealed[8..16] != chunk_id.to_le_bytes() {
+                anyhow::bail!("address mismatch");
+            }
+            for (p, s) in plain.iter_mut().zip(&sealed[16..]) {
+                *p = s ^ 0x5A;
+            }
+            Ok(())
+        }
+    }
+
+    fn members(name: &str) -> (MemberMap, Vec<std::path::PathBuf>) {
+        let mut map: MemberMap = BTreeMap::new();
+        let mut paths = Vec::new();
+        for hw_id in [1u64, 2] {
+            let path = std::env::temp_dir()
+                .join(format!("tuff_chain_{}_{}_{}.img", name, hw_id, std::process::id()));
+            map.insert(hw_id, Box::new(FileBlockDevice::create(&path, min_device_chunks() + 8).unwrap()));
+            paths.push(path);
+        }
+        (map, paths)
+    }
+
+    #[test]
+    fn chain_roundtrip_across_disks() {
+        let (map, paths) = members("roundtrip");
+        let data: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        let addrs = [
+            ChunkAddr { hw_id: 1, chunk_id: DATA_START + 3 },
+            ChunkAddr { hw_id: 2, chunk_id: DATA_START },
+            ChunkAddr { hw_id: 1, chunk_id: DATA_START + 1 },
+        ];
+        assert_eq!(chunks_needed(data.len() as u64, &TestCipher), 3);
+        write_chain(&map, &TestCipher, &addrs, &data).unwrap();
+
+        let back = read_chain(&map, &TestCipher, addrs[0], data.len() as u64).unwrap();
+        assert_eq!(back, data);
+        assert_eq!(chain_addrs(&map, &TestCipher, addrs[0]).unwrap(), addrs);
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn oversized_entry_size_is_a_mismatch_not_an_allocation() {
+        let (map, paths) = members("oversized");
+        let addr = ChunkAddr { hw_id: 1, chunk_id: DATA_START };
+        write_chain(&map, &TestCipher, &[addr], b"short").unwrap();
+        let err = read_chain(&map, &TestCipher, addr, u64::MAX).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ChainError>(),
+            Some(&ChainError::SizeMismatch { expected: u64::MAX, actual: 5 })
+        );
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn aes_chain_rejects_relocated_chunk() {
+        let (map, paths) = members("aes");
+        let cipher = chunk_cipher::ChunkCipher::new(&[3u8; 32]);
+        let data = vec![0x42u8; 100];
+        let addr = ChunkAddr { hw_id: 1, chunk_id: DATA_START };
+        write_chain(&map, &cipher, &[addr], &data).unwrap();
+        assert_eq!(read_chain(&map, &cipher, addr, 100).unwrap(), data);
+
+        // Copy the sealed chunk to another slot: authentication must fail there.
+        let mut raw = vec![0u8; CHUNK_SIZE];
+        map[&1].read_chunk(DATA_START, &mut raw).unwrap();
+        map[&1].write_chunk(DATA_START + 1, &raw).unwrap();
+        let moved = ChunkAddr { hw_id: 1, chunk_id: DATA_START + 1 };
+        let err = read_chain(&map, &cipher, moved, 100).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ChainError>(), Some(ChainError::Dangling { .. })));
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn detects_cycles_and_dangling_links() {
+        let (map, paths) = members("broken");
+        let a = ChunkAddr { hw_id: 1, chunk_id: DATA_START };
+        let b = ChunkAddr { hw_id: 2, chunk_id: DATA_START };
+
+        // Hand-build a -> b -> a.
+        for (addr, next) in [(a, b), (b, a)] {
+            let mut plain = vec![0u8; CHUNK_SIZE - 16];
+            plain[..DATA_CHUNK_HEADER_LEN]
+                .copy_from_slice(&DataChunkHeader::new(DATA_FLAG_VALID, 1, next.hw_id, next.chunk_id).0);
+            let mut sealed = vec![0u8; CHUNK_SIZE];
+            TestCipher.seal(addr.hw_id, addr.chunk_id, &plain, &mut sealed).unwrap();
+            map[&addr.hw_id].write_chunk(addr.chunk_id, &sealed).unwrap();
+        }
+        let err = read_chain(&map, &TestCipher, a, 2).unwrap_err();
+        assert_eq!(err.downcast_ref::<ChainError>(), Some(&ChainError::Cycle { hw_id: 1, chunk_id: DATA_START }));
+
+        // A never-written chunk fails to open.
+        let blank = ChunkAddr { hw_id: 2, chunk_id: DATA_START + 5 };
+        let err = read_chain(&map, &TestCipher, blank, 1).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ChainError>(), Some(ChainError::Dangling { .. })));
+
+        // Unknown member disk.
+        let err = read_chain(&map, &TestCipher, ChunkAddr { hw_id: 9, chunk_id: DATA_START }, 1).unwrap_err();
+        assert!(matches!(err.downcast_ref::<ChainError>(), Some(ChainError::Dangling { .. })));
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+}
@@ -0,0 +1,435 @@
+// Existing-content detection, consulted before any destructive write.
+//
+// Only the first MiB is read: partition tables, boot sectors, filesystem
+// superblocks and volume headers all live there, and partition types are
+// taken from the tables rather than by reading each partition.
+use anyhow::Result;
+use std::fmt;
+
+use crate::block_device::{BlockDevice, CHUNK_SIZE};
+use crate::schemas::{parse_initial_chunk, validate_initial_chunk};
+
+/// Bytes read from the start of the device.
+pub const PROBE_LEN: usize = 1024 * 1024;
+
+const GPT_SIGNATURE: &[u8; 8] = b"EFI PART";
+/// Upper bound on GPT entries examined (the usual table size).
+const GPT_MAX_ENTRIES: usize = 128;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filesystem {
+    Ntfs,
+    Fat,
+    Ext2,
+    Ext3,
+    Ext4,
+    Xfs,
+    Btrfs,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PartitionKind {
+    EfiSystem,
+    MicrosoftReserved,
+    MicrosoftBasicData,
+    WindowsRecovery,
+    LinuxFilesystem,
+    LinuxSwap,
+    LinuxLvm,
+    AppleApfs,
+    /// Unrecognized GPT type GUID.
+    OtherGpt(String),
+    /// Unrecognized MBR partition type byte.
+    OtherMbr(u8),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    pub kind: PartitionKind,
+    pub first_lba: u64,
+    pub sectors: u64,
+    /// GPT partition name; empty for MBR.
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Finding {
+    TuffVolume { volume_uuid: String },
+    Gpt { partitions: Vec<Partition> },
+    Mbr { partitions: Vec<Partition> },
+    Filesystem(Filesystem),
+    Luks { version: u16 },
+    LvmPhysicalVolume,
+}
+
+/// Everything recognized at the start of one device.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiskReport {
+    pub findings: Vec<Finding>,
+}
+
+impl DiskReport {
+    /// Nothing recognized: safe to overwrite.
+    pub fn is_blank(&self) -> bool {
+        self.findings.is_empty()
+    }
+
+    pub fn tuff_volume(&self) -> Option<&str> {
+        self.findings.iter().find_map(|f| match f {
+            Finding::TuffVolume { volume_uuid } => Some(volume_uuid.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Partitions from whichever table was found.
+    pub fn partitions(&self) -> &[Partition] {
+        self.findings
+            .iter()
+            .find_map(|f| match f {
+                Finding::Gpt { partitions } | Finding::Mbr { partitions } => Some(partitions.as_slice()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    }
+
+    /// An EFI system partition is the mark of an installed (bootable) OS.
+    pub fn has_efi_system_partition(&self) -> bool {
+        self.partitions().iter().any(|p| p.kind == PartitionKind::EfiSystem)
+    }
+}
+
+impl fmt::Display for DiskReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_blank() {
+            return write!(f, "no known signature");
+        }
+        let parts: Vec<String> = self.findings.iter().map(|x| x.to_string()).collect();
+        write!(f, "{}", parts.join("; "))
+    }
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Finding::TuffVolume { volume_uuid } => write!(f, "TUFF-FS volume {}", volume_uuid),
+            Finding::Gpt { partitions } | Finding::Mbr { partitions } => {
+                let table = if matches!(self, Finding::Gpt { .. }) { "GPT" } else { "MBR" };
+                let kinds: Vec<String> = partitions.iter().map(|p| format!("{:?}", p.kind)).collect();
+                write!(f, "{} partition table [{}]", table, kinds.join(", "))
+            }
+            Finding::Filesystem(fs) => write!(f, "{:?} filesystem", fs),
+            Finding::Luks { version } => write!(f, "LUKS{} container", version),
+            Finding::LvmPhysicalVolume => write!(f, "LVM physical volume"),
+        }
+    }
+}
+
+/// Reads the first `PROBE_LEN` bytes of `dev` (less if it is smaller) and
+/// reports what they hold.
+pub fn inspect(dev: &dyn BlockDevice) -> Result<DiskReport> {
+    let chunks = dev.chunk_count().min((PROBE_LEN / CHUNK_SIZE) as u64) as usize;
+    let mut head = vec![0u8; chunks * CHUNK_SIZE];
+    for (i, buf) in head.chunks_mut(CHUNK_SIZE).enumerate() {
+        dev.read_chunk(i as u64, buf)?;
+    }
+    Ok(scan(&head))
+}
+
+/// Recognizes signatures in `head`, the first bytes of a device.
+pub fn scan(head: &[u8]) -> DiskReport {
+    let mut findings = Vec::new();
+
+    if head.len() >= CHUNK_SIZE && validate_initial_chunk(&head[..CHUNK_SIZE]).is_ok() {
+        if let Ok(anchor) = parse_initial_chunk(&head[..CHUNK_SIZE]) {
+            let volume_uuid = anchor.volume_uuid().unwrap_or_default().to_string();
+            findings.push(Finding::TuffVolume { volume_uuid });
+        }
+        // An anchor occupies chunk 0; nothing else can be there.
+        return DiskReport { findings };
+    }
+
+    let gpt = [512usize, 4096].into_iter().find_map(|lba| gpt_partitions(head, lba));
+    if let Some(partitions) = gpt {
+        findings.push(Finding::Gpt { partitions });
+    } else if let Some(partitions) = mbr_partitions(head) {
+        findings.push(Finding::Mbr { partitions });
+    }
+    if let Some(version) = luks_version(head) {
+        findings.push(Finding::Luks { version });
+    }
+    if is_lvm_pv(head) {
+        findings.push(Finding::LvmPhysicalVolume);
+    }
+    if let Some(fs) = filesystem(head) {
+        findings.push(Finding::Filesystem(fs));
+    }
+    DiskReport { findings }
+}
+
+fn filesystem(head: &[u8]) -> Option<Filesystem> {
+    if at(head, 3, b"NTFS    ") {
+        return Some(Filesystem::Ntfs);
+    }
+    if at(head, 0, b"XFSB") {
+        return Some(Filesystem::Xfs);
+    }
+    if at(head, 0x10040, b"_BHRfS_M") {
+        return Some(Filesystem::Btrfs);
+    }
+    // ext superblock at 1024; s_magic at +56.
+    if at(head, 1024 + 56, &[0x53, 0xEF]) {
+        let compat = le_u32(head, 1024 + 0x5C)?;
+        let incompat = le_u32(head, 1024 + 0x60)?;
+        // EXTENTS | 64BIT | FLEX_BG
+        return Some(if incompat & (0x40 | 0x80 | 0x200) != 0 {
+            Filesystem::Ext4
+        } else if compat & 0x4 != 0 {
+            Filesystem::Ext3
+        } else {
+            Filesystem::Ext2
+        });
+    }
+    if at(head, 54, b"FAT") || at(head, 82, b"FAT32") {
+        return Some(Filesystem::Fat);
+    }
+    None
+}
+
+fn luks_version(head: &[u8]) -> Option<u16> {
+    if !at(head, 0, b"LUKS\xba\xbe") {
+        return None;
+    }
+    Some(u16::from_be_bytes(head.get(6..8)?.try_into().ok()?))
+}
+
+/// LVM2 label: "LABELONE" in one of the first four sectors, type "LVM2 001".
+fn is_lvm_pv(head: &[u8]) -> bool {
+    (0..4).any(|s| at(head, s * 512, b"LABELONE") && at(head, s * 512 + 24, b"LVM2 001"))
+}
+
+fn gpt_partitions(head: &[u8], lba_size: usize) -> Option<Vec<Partition>> {
+    if !at(head, lba_size, GPT_SIGNATURE) {
+        return None;
+    }
+    let entries_lba = le_u64(head, lba_size + 72)? as usize;
+    let count = (le_u32(head, lba_size + 80)? as usize).min(GPT_MAX_ENTRIES);
+    let entry_size = le_u32(head, lba_size + 84)? as usize;
+    if entry_size < 128 {
+        // Still a GPT disk, just not one whose entries can be read.
+        return Some(Vec::new());
+    }
+
+    let mut partitions = Vec::new();
+    for i in 0..count {
+        let Some(start) = entries_lba.checked_mul(lba_size).and_then(|s| s.checked_add(i * entry_size)) else {
+            break;
+        };
+        let Some(entry) = start.checked_add(128).and_then(|end| head.get(start..end)) else {
+            break;
+        };
+        let type_guid = &entry[0..16];
+        if type_guid.iter().all(|&b| b == 0) {
+            continue;
+        }
+        let first_lba = le_u64(entry, 32)?;
+        let last_lba = le_u64(entry, 40)?;
+        let name_units: Vec<u16> = entry[56..128]
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&u| u != 0)
+            .collect();
+        partitions.push(Partition {
+            kind: gpt_kind(&guid_string(type_guid)),
+            first_lba,
+            sectors: last_lba.saturating_sub(first_lba).saturating_add(1),
+            name: String::from_utf16_lossy(&name_units),
+        });
+    }
+    Some(partitions)
+}
+
+fn mbr_partitions(head: &[u8]) -> Option<Vec<Partition>> {
+    if !at(head, 510, &[0x55, 0xAA]) {
+        return None;
+    }
+    let partitions: Vec<Partition> = (0..4)
+        .filter_map(|i| {
+            let entry = &head[446 + i * 16..446 + (i + 1) * 16];
+            let kind = entry[4];
+            if kind == 0 {
+                return None;
+            }
+            Some(Partition {
+                kind: match kind {
+                    0xEF => PartitionKind::EfiSystem,
+                    0x07 => PartitionKind::MicrosoftBasicData,
+                    0x27 => PartitionKind::WindowsRecovery,
+                    0x83 => PartitionKind::LinuxFilesystem,
+                    0x82 => PartitionKind::LinuxSwap,
+                    0x8E => PartitionKind::LinuxLvm,
+                    other => PartitionKind::OtherMbr(other),
+                },
+                first_lba: u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64,
+                sectors: u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64,
+                name: String::new(),
+            })
+        })
+        .collect();
+    // A boot sector without a partition entry (e.g. a FAT volume) is no table.
+    if partitions.is_empty() {
+        None
+    } else {
+        Some(partitions)
+    }
+}
+
+fn gpt_kind(guid: &str) -> PartitionKind {
+    match guid {
+        "C12A7328-F81F-11D2-BA4B-00A0C93EC93B" => PartitionKind::EfiSystem,
+        "E3C9E316-0B5C-4DB8-817D-F92DF00215AE" => PartitionKind::MicrosoftReserved,
+        "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7" => PartitionKind::MicrosoftBasicData,
+        "DE94BBA4-06D1-4D40-A16A-BFD50179D6AC" => PartitionKind::WindowsRecovery,
+        "0FC63DAF-8483-4772-8E79-3D69D8477DE4" => PartitionKind::LinuxFilesystem,
+        "0657FD6D-A4AB-43C4-84E5-0933C84B4F4F" => PartitionKind::LinuxSwap,
+        "E6D6D379-F507-44C2-A23C-238F2A3DF928" => PartitionKind::LinuxLvm,
+        "7C3457EF-0000-11AA-AA11-00306543ECAC" => PartitionKind::AppleApfs,
+        other => PartitionKind::OtherGpt(other.to_string()),
+    }
+}
+
+/// GPT GUIDs store their first three fields little-endian.
+fn guid_string(b: &[u8]) -> String {
+    format!(
+        "{:08X}-{:04X}-{:04X}-{:02X}{:02X}-{}",
+        u32::from_le_bytes(b[0..4].try_into().unwrap()),
+        u16::from_le_bytes(b[4..6].try_into().unwrap()),
+        u16::from_le_bytes(b[6..8].try_into().unwrap()),
+        b[8],
+        b[9],
+        b[10..16].iter().map(|x| format!("{:02X}", x)).collect::<String>()
+    )
+}
+
+fn at(head: &[u8], offset: usize, magic: &[u8]) -> bool {
+    head.get(offset..offset + magic.len()) == Some(magic)
+}
+
+fn le_u32(buf: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn le_u64(buf: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(buf.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schemas::build_initial_chunk;
+
+    const ESP_GUID: [u8; 16] = [
+        0x28, 0x73, 0x2A, 0xC1, 0x1F, 0xF8, 0xD2, 0x11, 0xBA, 0x4B, 0x00, 0xA0, 0xC9, 0x3E, 0xC9, 0x3B,
+    ];
+
+    fn gpt_disk() -> Vec<u8> {
+        let mut head = vec![0u8; PROBE_LEN];
+        // Protective MBR
+        head[446 + 4] = 0xEE;
+        head[510] = 0x55;
+        head[511] = 0xAA;
+        head[512..520].copy_from_slice(GPT_SIGNATURE);
+        head[512 + 72..512 + 80].copy_from_slice(&2u64.to_le_bytes());
+        head[512 + 80..512 + 84].copy_from_slice(&128u32.to_le_bytes());
+        head[512 + 84..512 + 88].copy_from_slice(&128u32.to_le_bytes());
+        let entry = &mut head[1024..1152];
+        entry[0..16].copy_from_slice(&ESP_GUID);
+        entry[32..40].copy_from_slice(&2048u64.to_le_bytes());
+        entry[40..48].copy_from_slice(&1050623u64.to_le_bytes());
+        for (i, u) in "EFI".encode_utf16().enumerate() {
+            entry[56 + i * 2..58 + i * 2].copy_from_slice(&u.to_le_bytes());
+        }
+        head
+    }
+
+    #[test]
+    fn blank_and_tuff_disks() {
+        assert!(scan(&vec![0u8; PROBE_LEN]).is_blank());
+
+        let mut head = vec![0u8; PROBE_LEN];
+        let anchor = build_initial_chunk("vol-1", 7, &[1u8; 32], 4096).unwrap();
+        head[..CHUNK_SIZE].copy_from_slice(&anchor);
+        let report = scan(&head);
+        assert_eq!(report.tuff_volume(), Some("vol-1"));
+        assert_eq!(report.findings.len(), 1);
+    }
+
+    #[test]
+    fn gpt_with_efi_system_partition() {
+        let report = scan(&gpt_disk());
+        let parts = report.partitions();
+        assert_eq!(parts.len(), 1);
+        assert_eq!(parts[0].kind, PartitionKind::EfiSystem);
+        assert_eq!(parts[0].first_lba, 2048);
+        assert_eq!(parts[0].sectors, 1048576);
+        assert_eq!(parts[0].name, "EFI");
+        assert!(report.has_efi_system_partition());
+        // The protective MBR is not reported on its own.
+        assert!(!report.findings.iter().any(|f| matches!(f, Finding::Mbr { .. })));
+    }
+
+    #[test]
+    fn filesystems_and_containers() {
+        let mut ext4 = vec![0u8; PROBE_LEN];
+        ext4[1080..1082].copy_from_slice(&[0x53, 0xEF]);
+        ext4[1024 + 0x5C] = 0x4;
+        ext4[1024 + 0x60] = 0x40;
+        assert_eq!(scan(&ext4).findings, vec![Finding::Filesystem(Filesystem::Ext4)]);
+        ext4[1024 + 0x60] = 0;
+        assert_eq!(scan(&ext4).findings, vec![Finding::Filesystem(Filesystem::Ext3)]);
+
+        let mut btrfs = vec![0u8; PROBE_LEN];
+        btrfs[0x10040..0x10048].copy_from_slice(b"_BHRfS_M");
+        assert_eq!(scan(&btrfs).findings, vec![Finding::Filesystem(Filesystem::Btrfs)]);
+
+        let mut luks = vec![0u8; PROBE_LEN];
+        luks[0..8].copy_from_slice(b"LUKS\xba\xbe\x00\x02");
+        assert_eq!(scan(&luks).findings, vec![Finding::Luks { version: 2 }]);
+
+        let mut lvm = vec![0u8; PROBE_LEN];
+        lvm[512..520].copy_from_slice(b"LABELONE");
+        lvm[536..544].copy_from_slice(b"LVM2 001");
+        assert_eq!(scan(&lvm).findings, vec![Finding::LvmPhysicalVolume]);
+
+        let mut mbr_ntfs = vec![0u8; PROBE_LEN];
+        mbr_ntfs[3..11].copy_from_slice(b"NTFS    ");
+        mbr_ntfs[446 + 4] = 0x07;
+        mbr_ntfs[510..512].copy_from_slice(&[0x55, 0xAA]);
+        let report = scan(&mbr_ntfs);
+        assert_eq!(report.partitions()[0].kind, PartitionKind::MicrosoftBasicData);
+        assert!(report.findings.contains(&Finding::Filesystem(Filesystem::Ntfs)));
+    }
+
+    #[test]
+    fn short_device_is_scanned_safely() {
+        let head = gpt_disk();
+        // Truncated inside the entry array: the header is still recognized.
+        let report = scan(&head[..1100]);
+        assert!(matches!(report.findings[0], Finding::Gpt { ref partitions } if partitions.is_empty()));
+        assert!(scan(&head[..100]).is_blank());
+    }
+
+    #[test]
+    fn hostile_gpt_header_is_scanned_safely() {
+        for entries_lba in [u64::MAX, u64::MAX / 512, u64::MAX / 4096] {
+            let mut head = gpt_disk();
+            head[512 + 72..512 + 80].copy_from_slice(&entries_lba.to_le_bytes());
+            let report = scan(&head);
+            assert!(matches!(report.findings[0], Finding::Gpt { ref partitions } if partitions.is_empty()));
+        }
+
+        let mut head = gpt_disk();
+        head[1024 + 32..1024 + 40].copy_from_slice(&0u64.to_le_bytes());
+        head[1024 + 40..1024 + 48].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(scan(&head).partitions()[0].sectors, u64::MAX);
+    }
+}
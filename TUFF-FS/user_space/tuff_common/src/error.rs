@@ -1 +1,28 @@
-// Error types will live here.
+// Error types shared by TUFF-FS components.
+use thiserror::Error;
+
+/// Integrity failures while following a `DataChunkHeader` chain.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum ChainError {
+    #[error("chunk chain revisits hw_id={hw_id:#x} chunk_id={chunk_id}")]
+    Cycle { hw_id: u64, chunk_id: u64 },
+    #[error("dangling link to hw_id={hw_id:#x} chunk_id={chunk_id}: {reason}")]
+    Dangling { hw_id: u64, chunk_id: u64, reason: String },
+    #[error("chunk chain holds {actual} bytes, FileEntry expects {expected}")]
+    SizeMismatch { expected: u64, actual: u64 },
+}
+
+/// Breaks in the `prev_chunk_hash` chain linking IndexChunk generations.
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum IndexChainError {
+    #[error("chunk {position} of the chain is unreadable: {reason}")]
+    Unreadable { position: usize, reason: String },
+    #[error("generation {generation} follows generation {found}, expected {expected}")]
+    GenerationGap { generation: u8, expected: u8, found: u8 },
+    #[error("generation {generation} has no prev_chunk_hash but generation {prev} precedes it")]
+    MissingPrevHash { generation: u8, prev: u8 },
+    #[error("generation {generation}: prev_chunk_hash does not match generation {prev}")]
+    HashMismatch { generation: u8, prev: u8 },
+    #[error("generation {generation} links to generation {prev}, but no replica holds a matching copy")]
+    MissingPredecessor { generation: u8, prev: u8 },
+}
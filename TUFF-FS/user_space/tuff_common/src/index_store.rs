@@ -0,0 +1,334 @@
+// IndexChunk commit protocol over the index ring of every member disk.
+//
+// Commit of generation N (every member disk is a replica):
+//   1. write N with wrote_flag=false to slot N on all replicas, fsync
+//   2. rewrite slot N with wrote_flag=true on all replicas, fsync
+// A crash before step 2 completes leaves N uncommitted, so N-1 stays current.
+use anyhow::{Context, Result};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use tuff_schemas::tuff::tuff_os::FileEntryT;
+
+use crate::data_chunk::MemberMap;
+use crate::error::IndexChainError;
+use crate::layout::{
+    generation_newer, next_generation, prev_generation, read_index_slot, write_index_slot,
+    GENERATION_MAX,
+};
+use crate::schemas::{
+    build_index_chunk, index_chunk_hash, parse_index_chunk, validate_index_chunk, ChainLink,
+};
+
+/// A committed IndexChunk and the replicas (hw_ids) it was read from.
+#[derive(Debug, Clone)]
+pub struct LoadedIndex {
+    pub generation: u8,
+    pub timestamp: i64,
+    pub data: Vec<u8>,
+    pub replicas: Vec<u64>,
+}
+
+/// Reads every committed generation from every replica, deduplicated by content.
+pub fn load_committed(members: &MemberMap) -> Result<Vec<LoadedIndex>> {
+    let mut found: Vec<LoadedIndex> = Vec::new();
+    for (&hw_id, dev) in members {
+        for generation in 1..=GENERATION_MAX {
+            let data = match read_index_slot(dev.as_ref(), generation) {
+                Ok(Some(data)) => data,
+                // A damaged slot on one replica must not hide the others.
+                Ok(None) | Err(_) => continue,
+            };
+            if validate_index_chunk(&data).is_err() {
+                continue;
+            }
+            let header = parse_index_chunk(&data)?.header();
+            if header.generation() != generation {
+                continue;
+            }
+            match found.iter_mut().find(|l| l.data == data) {
+                Some(existing) => existing.replicas.push(hw_id),
+                None => found.push(LoadedIndex {
+                    generation,
+                    timestamp: header.timestamp(),
+                    data,
+                    replicas: vec![hw_id],
+                }),
+            }
+        }
+    }
+    Ok(found)
+}
+
+/// Picks the newest committed generation across all replicas.
+///
+/// The head is the generation no other committed chunk names as its
+/// predecessor; ties (e.g. a broken chain) go to the later timestamp and then
+/// to the newer generation on the ring.
+pub fn load_latest(members: &MemberMap) -> Result<Option<LoadedIndex>> {
+    let committed = load_committed(members)?;
+    let prev_hashes: Vec<Vec<u8>> = committed
+        .iter()
+        .filter_map(|l| {
+            parse_index_chunk(&l.data)
+                .ok()
+                .and_then(|c| c.header().prev_chunk_hash().map(|h| h.to_vec()))
+        })
+        .collect();
+
+    let head = committed
+        .into_iter()
+        .filter(|l| {
+            let hash = index_chunk_hash(&l.data);
+            !prev_hashes.iter().any(|p| p.as_slice() == hash)
+        })
+        .max_by(|a, b| {
+            a.timestamp.cmp(&b.timestamp).then_with(|| {
+                if generation_newer(a.generation, b.generation) {
+                    std::cmp::Ordering::Greater
+                } else if a.generation == b.generation {
+                    std::cmp::Ordering::Equal
+                } else {
+                    std::cmp::Ordering::Less
+                }
+            })
+        });
+    Ok(head)
+}
+
+/// Collects `head` and the committed generations before it, newest first.
+///
+/// Where replicas disagree on a generation, the copy matching the successor's
+/// `prev_chunk_hash` is taken. The walk ends at the genesis chunk (no
+/// `prev_chunk_hash`), at `head`'s history floor, or one step short of
+/// wrapping back onto `head`'s own slot. Anywhere else a missing predecessor
+/// is a broken chain: an empty slot or a copy with the wrong hash.
+pub fn load_history(members: &MemberMap, head: &LoadedIndex) -> Result<Vec<Vec<u8>>> {
+    let committed = load_committed(members)?;
+    let floor = parse_index_chunk(&head.data)?.header().history_floor();
+    let mut history = vec![head.data.clone()];
+    let mut generation = head.generation;
+
+    loop {
+        let newest = parse_index_chunk(history.last().expect("starts with head"))?;
+        let Some(expected) = newest.header().prev_chunk_hash() else {
+            break;
+        };
+        if generation == floor {
+            break;
+        }
+        let prev = prev_generation(generation);
+        if prev == head.generation {
+            break;
+        }
+        let older = committed
+            .iter()
+            .filter(|l| l.generation == prev)
+            .find(|l| index_chunk_hash(&l.data) == expected)
+            .ok_or(IndexChainError::MissingPredecessor { generation, prev })?;
+        history.push(older.data.clone());
+        generation = prev;
+    }
+    Ok(history)
+}
+
+/// Commits `entries` as the generation after `prev`, keeping its volume
+/// settings and history floor.
+pub fn commit_next(
+    members: &MemberMap,
+    prev: &LoadedIndex,
+    entries: &[FileEntryT],
+) -> Result<LoadedIndex> {
+    let floor = parse_index_chunk(&prev.data)?.header().history_floor();
+    // Once the ring comes round to the floor's slot, the floor itself is
+    // overwritten and the walk stops at the wrap instead.
+    let floor = if floor == next_generation(prev.generation) { 0 } else { floor };
+    commit_with_floor(members, prev, entries, floor)
+}
+
+/// `commit_next` with an explicit history floor, as set by a truncate.
+pub fn commit_with_floor(
+    members: &MemberMap,
+    prev: &LoadedIndex,
+    entries: &[FileEntryT],
+    history_floor: u8,
+) -> Result<LoadedIndex> {
+    if members.is_empty() {
+        anyhow::bail!("no replicas to commit to");
+    }
+    let prev_chunk = parse_index_chunk(&prev.data)?;
+    let prev_header = prev_chunk.header();
+    let volume_name = prev_header.volume_name().unwrap_or_default();
+    let redundancy = prev_header.default_redundancy();
+
+    let generation = next_generation(prev.generation);
+    let prev_hash = index_chunk_hash(&prev.data);
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("time went backwards")?
+        .as_secs() as i64;
+    // Never go backwards: load_latest breaks ties by timestamp.
+    let timestamp = timestamp.max(prev.timestamp);
+
+    let link = ChainLink { prev_chunk_hash: Some(&prev_hash), history_floor };
+    let pending = build_index_chunk(generation, false, timestamp, volume_name, redundancy, link, entries)?;
+    let committed = build_index_chunk(generation, true, timestamp, volume_name, redundancy, link, entries)?;
+
+    // Phase 1: pending copy everywhere.
+    for dev in members.values() {
+        write_index_slot(dev.as_ref(), generation, &pending)?;
+    }
+    for dev in members.values() {
+        dev.flush()?;
+    }
+
+    // Phase 2: flip to committed.
+    for dev in members.values() {
+        write_index_slot(dev.as_ref(), generation, &committed)?;
+    }
+    for dev in members.values() {
+        dev.flush()?;
+    }
+
+    Ok(LoadedIndex {
+        generation,
+        timestamp,
+        data: committed,
+        replicas: members.keys().copied().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device::FileBlockDevice;
+    use crate::layout::min_device_chunks;
+    use crate::schemas::build_minimal_index_chunk;
+    use std::collections::BTreeMap;
+
+    fn replicas(name: &str) -> (MemberMap, Vec<std::path::PathBuf>) {
+        let mut map: MemberMap = BTreeMap::new();
+        let mut paths = Vec::new();
+        let first = build_minimal_index_chunk("vol", 2).unwrap();
+        for hw_id in [1u64, 2] {
+            let path = std::env::temp_dir()
+                .join(format!("tuff_index_{}_{}_{}.img", name, hw_id, std::process::id()));
+            let dev = FileBlockDevice::create(&path, min_device_chunks()).unwrap();
+            write_index_slot(&dev, 1, &first).unwrap();
+            map.insert(hw_id, Box::new(dev));
+            paths.push(path);
+        }
+        (map, paths)
+    }
+
+    #[test]
+    fn commit_chains_and_load_picks_newest() {
+        let (map, paths) = replicas("commit");
+        let first = load_latest(&map).unwrap().unwrap();
+        assert_eq!(first.generation, 1);
+        assert_eq!(first.replicas, vec![1, 2]);
+
+        let second = commit_next(&map, &first, &[]).unwrap();
+        let third = commit_next(&map, &second, &[]).unwrap();
+        let latest = load_latest(&map).unwrap().unwrap();
+        assert_eq!(latest.generation, 3);
+        assert_eq!(latest.data, third.data);
+
+        let chunk = parse_index_chunk(&latest.data).unwrap();
+        assert_eq!(chunk.header().prev_chunk_hash(), Some(&index_chunk_hash(&second.data)[..]));
+        assert_eq!(chunk.header().default_redundancy(), 2);
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn uncommitted_generation_is_ignored() {
+        let (map, paths) = replicas("crash");
+        let first = load_latest(&map).unwrap().unwrap();
+
+        // Simulate a crash after phase 1 on one replica.
+        let pending = build_index_chunk(
+            2, false, first.timestamp + 10, "vol", 2, ChainLink::after(&index_chunk_hash(&first.data)), &[],
+        )
+        .unwrap();
+        write_index_slot(map[&1].as_ref(), 2, &pending).unwrap();
+
+        let latest = load_latest(&map).unwrap().unwrap();
+        assert_eq!(latest.generation, 1);
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn head_follows_chain_across_wraparound() {
+        let (map, paths) = replicas("wrap");
+        let prev = load_latest(&map).unwrap().unwrap();
+
+        // Generation 254 followed by 1 with the same timestamp: 1 is the head.
+        let g254 = build_index_chunk(254, true, prev.timestamp, "vol", 2, ChainLink::default(), &[]).unwrap();
+        let g1 = build_index_chunk(
+            1, true, prev.timestamp, "vol", 2, ChainLink::after(&index_chunk_hash(&g254)), &[],
+        )
+        .unwrap();
+        for d in map.values() {
+            write_index_slot(d.as_ref(), 254, &g254).unwrap();
+            write_index_slot(d.as_ref(), 1, &g1).unwrap();
+        }
+        let latest = load_latest(&map).unwrap().unwrap();
+        assert_eq!(latest.generation, 1);
+        assert_eq!(latest.data, g1);
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn history_walk_detects_tampered_replica() {
+        let (map, paths) = replicas("history");
+        let first = load_latest(&map).unwrap().unwrap();
+        let second = commit_next(&map, &first, &[]).unwrap();
+        let third = commit_next(&map, &second, &[]).unwrap();
+
+        let history = load_history(&map, &third).unwrap();
+        assert_eq!(history, vec![third.data.clone(), second.data.clone(), first.data.clone()]);
+        let refs: Vec<&[u8]> = history.iter().map(|d| d.as_slice()).collect();
+        crate::schemas::verify_index_chain(&refs).unwrap();
+
+        // Both replicas hold a rewritten generation 2: the link from 3 breaks.
+        let forged = build_index_chunk(
+            2, true, second.timestamp, "forged", 2, ChainLink::after(&index_chunk_hash(&first.data)), &[],
+        )
+        .unwrap();
+        for d in map.values() {
+            write_index_slot(d.as_ref(), 2, &forged).unwrap();
+        }
+        let err = load_history(&map, &third).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IndexChainError>(),
+            Some(&IndexChainError::MissingPredecessor { generation: 3, prev: 2 })
+        );
+
+        // Emptying generation 2 everywhere is no way around it either.
+        for d in map.values() {
+            crate::layout::clear_index_slot(d.as_ref(), 2).unwrap();
+        }
+        let err = load_history(&map, &third).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<IndexChainError>(),
+            Some(&IndexChainError::MissingPredecessor { generation: 3, prev: 2 })
+        );
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+
+    #[test]
+    fn history_floor_ends_the_walk_and_is_inherited() {
+        let (map, paths) = replicas("floor");
+        let first = load_latest(&map).unwrap().unwrap();
+        let second = commit_next(&map, &first, &[]).unwrap();
+        let third = commit_with_floor(&map, &second, &[], 2).unwrap();
+        let fourth = commit_next(&map, &third, &[]).unwrap();
+        for d in map.values() {
+            crate::layout::clear_index_slot(d.as_ref(), 1).unwrap();
+        }
+
+        let history = load_history(&map, &fourth).unwrap();
+        assert_eq!(history, vec![fourth.data.clone(), third.data.clone(), second.data.clone()]);
+        assert_eq!(parse_index_chunk(&fourth.data).unwrap().header().history_floor(), 2);
+        paths.iter().for_each(|p| std::fs::remove_file(p).unwrap());
+    }
+}
@@ -0,0 +1,146 @@
+// On-disk layout of a TUFF-FS member disk, in CHUNK_SIZE units:
+//
+//   chunk 0                     InitialChunk (physical anchor)
+//   chunks 1 .. DATA_START      index ring: one fixed slot per generation 1-254
+//   chunks DATA_START ..        data chunks
+use anyhow::{Context, Result};
+
+use crate::block_device::{BlockDevice, CHUNK_SIZE};
+
+pub const INITIAL_CHUNK_INDEX: u64 = 0;
+pub const INDEX_RING_START: u64 = 1;
+/// Chunks reserved per index generation (256 KiB).
+pub const INDEX_SLOT_CHUNKS: u64 = 64;
+/// One slot per generation value; generation N lives in slot N-1.
+pub const INDEX_RING_SLOTS: u64 = 254;
+pub const DATA_START: u64 = INDEX_RING_START + INDEX_RING_SLOTS * INDEX_SLOT_CHUNKS;
+
+/// Largest serialized IndexChunk a slot can hold (slot minus the length prefix).
+pub const INDEX_SLOT_CAPACITY: usize = INDEX_SLOT_CHUNKS as usize * CHUNK_SIZE - 4;
+
+/// Generations cycle through 1..=254.
+pub const GENERATION_MAX: u8 = INDEX_RING_SLOTS as u8;
+
+pub fn next_generation(generation: u8) -> u8 {
+    if generation >= GENERATION_MAX {
+        1
+    } else {
+        generation + 1
+    }
+}
+
+/// Serial-number comparison on the 1..=254 ring: `a` is newer than `b` when it
+/// lies less than half the ring ahead, so 1 is newer than 254.
+pub fn generation_newer(a: u8, b: u8) -> bool {
+    let ring = GENERATION_MAX as i16;
+    let distance = (a as i16 - b as i16).rem_euclid(ring);
+    distance != 0 && distance < ring / 2
+}
+
+pub fn prev_generation(generation: u8) -> u8 {
+    if generation <= 1 {
+        GENERATION_MAX
+    } else {
+        generation - 1
+    }
+}
+
+pub fn index_slot_start(generation: u8) -> Result<u64> {
+    if generation == 0 || generation as u64 > INDEX_RING_SLOTS {
+        anyhow::bail!("invalid generation: {}", generation);
+    }
+    Ok(INDEX_RING_START + (generation as u64 - 1) * INDEX_SLOT_CHUNKS)
+}
+
+/// Smallest device that can hold the anchor, the index ring and one data chunk.
+pub fn min_device_chunks() -> u64 {
+    DATA_START + 1
+}
+
+/// Writes `data` to the slot of `generation` as `[u32 LE length][data][zero padding]`.
+/// Only the chunks covering the payload are written.
+pub fn write_index_slot(dev: &dyn BlockDevice, generation: u8, data: &[u8]) -> Result<()> {
+    if data.len() > INDEX_SLOT_CAPACITY {
+        anyhow::bail!(
+            "IndexChunk of {} bytes exceeds slot capacity {}",
+            data.len(),
+            INDEX_SLOT_CAPACITY
+        );
+    }
+    let start = index_slot_start(generation)?;
+
+    let mut framed = Vec::with_capacity(4 + data.len() + CHUNK_SIZE);
+    framed.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    framed.extend_from_slice(data);
+    let padded = framed.len().div_ceil(CHUNK_SIZE) * CHUNK_SIZE;
+    framed.resize(padded, 0);
+
+    for (i, chunk) in framed.chunks(CHUNK_SIZE).enumerate() {
+        dev.write_chunk(start + i as u64, chunk)
+            .with_context(|| format!("Failed to write index slot {}", generation))?;
+    }
+    Ok(())
+}
+
+/// Empties the slot of `generation` by zeroing its length prefix.
+pub fn clear_index_slot(dev: &dyn BlockDevice, generation: u8) -> Result<()> {
+    dev.write_chunk(index_slot_start(generation)?, &[0u8; CHUNK_SIZE])
+        .with_context(|| format!("Failed to clear index slot {}", generation))
+}
+
+/// Reads the slot of `generation`; returns `None` for an empty slot.
+pub fn read_index_slot(dev: &dyn BlockDevice, generation: u8) -> Result<Option<Vec<u8>>> {
+    let start = index_slot_start(generation)?;
+    let mut chunk = vec![0u8; CHUNK_SIZE];
+    dev.read_chunk(start, &mut chunk)?;
+
+    let len = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]) as usize;
+    if len == 0 {
+        return Ok(None);
+    }
+    if len > INDEX_SLOT_CAPACITY {
+        anyhow::bail!("index slot {} has corrupt length {}", generation, len);
+    }
+
+    let mut data = Vec::with_capacity(4 + len);
+    data.extend_from_slice(&chunk);
+    let total_chunks = (4 + len).div_ceil(CHUNK_SIZE) as u64;
+    for i in 1..total_chunks {
+        dev.read_chunk(start + i, &mut chunk)?;
+        data.extend_from_slice(&chunk);
+    }
+    data.truncate(4 + len);
+    data.drain(..4);
+    Ok(Some(data))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device::FileBlockDevice;
+
+    #[test]
+    fn generation_wraparound() {
+        assert_eq!(next_generation(1), 2);
+        assert_eq!(next_generation(254), 1);
+        assert_eq!(prev_generation(1), 254);
+        assert!(generation_newer(2, 1));
+        assert!(generation_newer(1, 254));
+        assert!(generation_newer(3, 250));
+        assert!(!generation_newer(254, 1));
+        assert!(!generation_newer(7, 7));
+    }
+
+    #[test]
+    fn index_slot_roundtrip_spanning_chunks() {
+        let path = std::env::temp_dir().join(format!("tuff_layout_{}.img", std::process::id()));
+        let dev = FileBlockDevice::create(&path, min_device_chunks()).unwrap();
+
+        assert!(read_index_slot(&dev, 254).unwrap().is_none());
+        let data: Vec<u8> = (0..(CHUNK_SIZE * 2 + 17)).map(|i| i as u8).collect();
+        write_index_slot(&dev, 254, &data).unwrap();
+        assert_eq!(read_index_slot(&dev, 254).unwrap(), Some(data));
+        assert!(index_slot_start(0).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,4 +1,12 @@
+pub mod block_device;
+pub mod control;
+pub mod data_chunk;
+pub mod disk_detect;
 pub mod error;
+pub mod index_store;
+pub mod layout;
 pub mod paths;
 pub mod schemas;
+pub mod state;
+pub mod truncate;
 pub use tuff_schemas;
@@ -1,5 +1,6 @@
 // Path constants for TUFF-OS runtime state.
-pub const INDEX_DIR: &str = "/var/lib/tuff/index";
-pub const INDEX_CHUNK_CURRENT: &str = "/var/lib/tuff/index/index_chunk.bin";
-pub const INDEX_CHUNK_PREV: &str = "/var/lib/tuff/index/index_chunk.prev";
 pub const MK_FINGERPRINT_PATH: &str = "/var/lib/tuff/mk_fingerprint";
+pub const CONTROL_SOCKET_PATH: &str = "/run/tuff/tuffd.sock";
+pub const WRITE_QUEUE_DIR: &str = "/var/lib/tuff/queue";
+pub const TUFFD_CONFIG_PATH: &str = "/etc/tuff/tuffd.json";
+pub const SYSTEM_UUID_PATH: &str = "/sys/class/dmi/id/product_uuid";
@@ -1,14 +1,63 @@
 use anyhow::{Context, Result};
 
+use sha2::{Digest, Sha256};
 use std::time::{SystemTime, UNIX_EPOCH};
 use tuff_schemas::tuff;
 
+use crate::block_device::CHUNK_SIZE;
+use crate::error::IndexChainError;
+use crate::layout::{prev_generation, GENERATION_MAX};
+
+/// InitialChunk signature: "TUFFFS01" read as a little-endian u64.
+pub const INITIAL_CHUNK_MAGIC: u64 = u64::from_le_bytes(*b"TUFFFS01");
+pub use tuff_crypto::key_manager::FINGERPRINT_LEN as MK_FINGERPRINT_LEN;
+
 pub fn parse_index_chunk(buf: &[u8]) -> Result<tuff::tuff_os::IndexChunk<'_>> {
     tuff::tuff_os::root_as_index_chunk(buf)
         .context("invalid IndexChunk flatbuffer")
 }
 
 pub fn build_minimal_index_chunk(volume_name: &str, default_redundancy: u8) -> Result<Vec<u8>> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("time went backwards")?
+        .as_secs() as i64;
+    build_index_chunk(1, true, timestamp, volume_name, default_redundancy, ChainLink::default(), &[])
+}
+
+/// Where an IndexChunk sits in the history chain.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ChainLink<'a> {
+    /// SHA-256 of the preceding generation; `None` for the first one.
+    pub prev_chunk_hash: Option<&'a [u8]>,
+    /// Oldest generation kept by the last truncate; 0 when there is none.
+    pub history_floor: u8,
+}
+
+impl<'a> ChainLink<'a> {
+    /// Follows the chunk hashing to `prev_hash`, with no history floor.
+    pub fn after(prev_hash: &'a [u8]) -> Self {
+        Self { prev_chunk_hash: Some(prev_hash), history_floor: 0 }
+    }
+}
+
+/// Builds an IndexChunk with every scalar forced into the buffer, so the pending
+/// (`wrote_flag=false`) and committed forms of one generation differ in a single byte.
+pub fn build_index_chunk(
+    generation: u8,
+    wrote_flag: bool,
+    timestamp: i64,
+    volume_name: &str,
+    default_redundancy: u8,
+    link: ChainLink<'_>,
+    entries: &[tuff::tuff_os::FileEntryT],
+) -> Result<Vec<u8>> {
+    if generation == 0 || generation > 254 {
+        anyhow::bail!("invalid generation: {}", generation);
+    }
+    if link.history_floor > GENERATION_MAX || link.history_floor == generation {
+        anyhow::bail!("invalid history_floor {} for generation {}", link.history_floor, generation);
+    }
     if volume_name.is_empty() {
         anyhow::bail!("volume_name is empty");
     }
@@ -16,24 +65,21 @@ pub fn build_minimal_index_chunk(volume_name: &str, default_redundancy: u8) -> R
         anyhow::bail!("invalid default_redundancy: {}", default_redundancy);
     }
 
-    let timestamp = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .context("time went backwards")?
-        .as_secs() as i64;
-
     let mut header = tuff::tuff_os::IndexChunkHeaderT::default();
-    header.generation = 1;
-    header.wrote_flag = true;
+    header.generation = generation;
+    header.wrote_flag = wrote_flag;
     header.timestamp = timestamp;
     header.default_redundancy = default_redundancy;
     header.volume_name = Some(volume_name.to_string());
-    header.prev_chunk_hash = None;
+    header.prev_chunk_hash = link.prev_chunk_hash.map(|h| h.to_vec());
+    header.history_floor = link.history_floor;
 
     let mut chunk = tuff::tuff_os::IndexChunkT::default();
-    chunk.header = Box::new(header);
-    chunk.entries = Some(Vec::new());
+    *chunk.header = header;
+    chunk.entries = Some(entries.to_vec());
 
     let mut builder = flatbuffers::FlatBufferBuilder::new();
+    builder.force_defaults(true);
     let root = chunk.pack(&mut builder);
     tuff::tuff_os::finish_index_chunk_buffer(&mut builder, root);
     Ok(builder.finished_data().to_vec())
@@ -61,5 +107,193 @@ pub fn validate_index_chunk(buf: &[u8]) -> Result<()> {
     if redundancy == 0 {
         anyhow::bail!("invalid default_redundancy: {}", redundancy);
     }
+    let floor = header.history_floor();
+    if floor > GENERATION_MAX || floor == generation {
+        anyhow::bail!("invalid history_floor: {}", floor);
+    }
+    Ok(())
+}
+
+/// SHA-256 of a serialized IndexChunk, as stored in its successor's `prev_chunk_hash`.
+pub fn index_chunk_hash(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Checks the `prev_chunk_hash` links of `chain`, ordered newest first.
+///
+/// Each chunk must name the SHA-256 of the next (older) one and be exactly one
+/// generation ahead of it on the 1..=254 ring. The error carries the generation
+/// whose link is broken.
+pub fn verify_index_chain(chain: &[&[u8]]) -> Result<(), IndexChainError> {
+    let chunks = chain
+        .iter()
+        .enumerate()
+        .map(|(position, data)| {
+            parse_index_chunk(data).map_err(|e| IndexChainError::Unreadable {
+                position,
+                reason: e.to_string(),
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    for (i, pair) in chunks.windows(2).enumerate() {
+        let (newer, older) = (pair[0].header(), pair[1].header());
+        let generation = newer.generation();
+        let prev = older.generation();
+        if prev != prev_generation(generation) {
+            return Err(IndexChainError::GenerationGap {
+                generation,
+                expected: prev_generation(generation),
+                found: prev,
+            });
+        }
+        match newer.prev_chunk_hash() {
+            None => return Err(IndexChainError::MissingPrevHash { generation, prev }),
+            Some(hash) if hash != index_chunk_hash(chain[i + 1]) => {
+                return Err(IndexChainError::HashMismatch { generation, prev });
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+pub fn parse_initial_chunk(buf: &[u8]) -> Result<tuff::tuff_os::InitialChunk<'_>> {
+    flatbuffers::root::<tuff::tuff_os::InitialChunk>(buf)
+        .context("invalid InitialChunk flatbuffer")
+}
+
+/// Builds the LBA 0 anchor, zero-padded to exactly one chunk.
+pub fn build_initial_chunk(
+    volume_uuid: &str,
+    hw_id: u64,
+    mk_fingerprint: &[u8],
+    sector_size: u32,
+) -> Result<Vec<u8>> {
+    if volume_uuid.is_empty() {
+        anyhow::bail!("volume_uuid is empty");
+    }
+    if hw_id == 0 {
+        anyhow::bail!("invalid hw_id: 0");
+    }
+    check_mk_fingerprint(mk_fingerprint)?;
+    check_sector_size(sector_size)?;
+
+    let created_at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .context("time went backwards")?
+        .as_secs() as i64;
+
+    let mut chunk = tuff::tuff_os::InitialChunkT::default();
+    chunk.magic = INITIAL_CHUNK_MAGIC;
+    chunk.volume_uuid = Some(volume_uuid.to_string());
+    chunk.hw_id = hw_id;
+    chunk.mk_fingerprint = Some(mk_fingerprint.to_vec());
+    chunk.created_at = created_at;
+    chunk.sector_size = sector_size;
+
+    let mut builder = flatbuffers::FlatBufferBuilder::new();
+    let root = chunk.pack(&mut builder);
+    builder.finish(root, None);
+    let data = builder.finished_data();
+    if data.len() > CHUNK_SIZE {
+        anyhow::bail!("InitialChunk exceeds {} bytes: {}", CHUNK_SIZE, data.len());
+    }
+    let mut out = vec![0u8; CHUNK_SIZE];
+    out[..data.len()].copy_from_slice(data);
+    Ok(out)
+}
+
+pub fn validate_initial_chunk(buf: &[u8]) -> Result<()> {
+    let chunk = parse_initial_chunk(buf)?;
+    if chunk.magic() != INITIAL_CHUNK_MAGIC {
+        anyhow::bail!("bad InitialChunk magic: {:#018x}", chunk.magic());
+    }
+    match chunk.volume_uuid() {
+        Some(uuid) if !uuid.is_empty() => {}
+        Some(_) => anyhow::bail!("volume_uuid is empty"),
+        None => anyhow::bail!("missing volume_uuid"),
+    }
+    if chunk.hw_id() == 0 {
+        anyhow::bail!("invalid hw_id: 0");
+    }
+    match chunk.mk_fingerprint() {
+        Some(fp) => check_mk_fingerprint(fp)?,
+        None => anyhow::bail!("missing mk_fingerprint"),
+    }
+    if chunk.created_at() <= 0 {
+        anyhow::bail!("invalid created_at: {}", chunk.created_at());
+    }
+    check_sector_size(chunk.sector_size())
+}
+
+fn check_mk_fingerprint(fp: &[u8]) -> Result<()> {
+    if fp.len() != MK_FINGERPRINT_LEN {
+        anyhow::bail!(
+            "invalid mk_fingerprint length: {} (expected {})",
+            fp.len(),
+            MK_FINGERPRINT_LEN
+        );
+    }
     Ok(())
 }
+
+fn check_sector_size(sector_size: u32) -> Result<()> {
+    // A chunk must cover a whole number of physical sectors.
+    if !sector_size.is_power_of_two() || !(512..=CHUNK_SIZE as u32).contains(&sector_size) {
+        anyhow::bail!("invalid sector_size: {}", sector_size);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn initial_chunk_roundtrip() {
+        let buf = build_initial_chunk("vol-1", 42, &[7u8; MK_FINGERPRINT_LEN], 4096).unwrap();
+        assert_eq!(buf.len(), CHUNK_SIZE);
+        validate_initial_chunk(&buf).unwrap();
+
+        let chunk = parse_initial_chunk(&buf).unwrap();
+        assert_eq!(chunk.volume_uuid(), Some("vol-1"));
+        assert_eq!(chunk.hw_id(), 42);
+    }
+
+    #[test]
+    fn initial_chunk_rejects_bad_inputs() {
+        let fp = [0u8; MK_FINGERPRINT_LEN];
+        assert!(build_initial_chunk("vol", 1, &fp[..16], 4096).is_err());
+        assert!(build_initial_chunk("vol", 1, &fp, 1000).is_err());
+        assert!(build_initial_chunk("vol", 1, &fp, 8192).is_err());
+        assert!(build_initial_chunk("", 1, &fp, 512).is_err());
+    }
+
+    #[test]
+    fn blank_chunk_is_not_an_initial_chunk() {
+        assert!(validate_initial_chunk(&[0u8; CHUNK_SIZE]).is_err());
+    }
+
+    #[test]
+    fn index_chain_reports_broken_generation() {
+        let g1 = build_index_chunk(1, true, 10, "vol", 1, ChainLink::default(), &[]).unwrap();
+        let g2 = build_index_chunk(2, true, 11, "vol", 1, ChainLink::after(&index_chunk_hash(&g1)), &[]).unwrap();
+        let g3 = build_index_chunk(3, true, 12, "vol", 1, ChainLink::after(&index_chunk_hash(&g2)), &[]).unwrap();
+        verify_index_chain(&[&g3, &g2, &g1]).unwrap();
+
+        let forged = build_index_chunk(2, true, 11, "other", 1, ChainLink::after(&index_chunk_hash(&g1)), &[]).unwrap();
+        assert_eq!(
+            verify_index_chain(&[&g3, &forged, &g1]),
+            Err(IndexChainError::HashMismatch { generation: 3, prev: 2 })
+        );
+        assert_eq!(
+            verify_index_chain(&[&g3, &g1]),
+            Err(IndexChainError::GenerationGap { generation: 3, expected: 2, found: 1 })
+        );
+        assert!(matches!(
+            verify_index_chain(&[&g3, b"junk"]),
+            Err(IndexChainError::Unreadable { position: 1, .. })
+        ));
+    }
+}
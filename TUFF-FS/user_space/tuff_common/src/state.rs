@@ -0,0 +1,13 @@
+use serde::{Deserialize, Serialize};
+
+/// Operating state of tuffd, shared with tuffctl over the control socket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum State {
+    Init,
+    WaitKey,
+    Normal,
+    Warn,
+    Freeze,
+    PendingOnly,
+    Shutdown,
+}
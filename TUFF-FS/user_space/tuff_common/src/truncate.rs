@@ -0,0 +1,235 @@
+// Pruning of old IndexChunk generations.
+//
+// A data chunk is free once no retained generation reaches it through a
+// FileEntry chain. Truncate first commits a generation whose history floor is
+// the oldest one retained, so the history walk no longer expects anything
+// older. Only then are the dropped index slots cleared and, last, the chunks
+// they alone referenced discarded: an interrupted truncate can leak chunks but
+// never free one that is still in use.
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+
+use crate::block_device::CHUNK_SIZE;
+use crate::data_chunk::{chain_addrs, ChunkAddr, ChunkCipher, MemberMap};
+use crate::index_store::{commit_with_floor, load_committed, load_history, LoadedIndex};
+use crate::layout::{clear_index_slot, next_generation};
+use crate::schemas::parse_index_chunk;
+
+/// Which generations survive a truncate. The head is always kept.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Retention {
+    /// Keep at most this many generations, counting the head.
+    pub keep: Option<usize>,
+    /// Drop generations whose timestamp is older than this many seconds.
+    pub max_age_secs: Option<i64>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TruncatePlan {
+    /// Generations kept, newest first.
+    pub retained: Vec<u8>,
+    pub dropped: Vec<u8>,
+    /// Data chunks referenced only by dropped generations.
+    pub freed: Vec<ChunkAddr>,
+}
+
+/// Works out what a truncate at time `now` would drop and free, without writing.
+///
+/// Fails if any chain of a retained or dropped generation cannot be followed.
+pub fn plan(
+    members: &MemberMap,
+    cipher: &dyn ChunkCipher,
+    head: &LoadedIndex,
+    retention: &Retention,
+    now: i64,
+) -> Result<TruncatePlan> {
+    let history = load_history(members, head)?;
+    let mut retained = Vec::new();
+    let mut referenced = BTreeSet::new();
+
+    for (i, data) in history.iter().enumerate() {
+        let chunk = parse_index_chunk(data)?;
+        let header = chunk.header();
+        let within_count = retention.keep.is_none_or(|k| i < k);
+        let within_age = retention.max_age_secs.is_none_or(|a| now - header.timestamp() <= a);
+        if i > 0 && !(within_count && within_age) {
+            break;
+        }
+        retained.push(header.generation());
+        for entry in chunk.entries().iter().flatten() {
+            let start = ChunkAddr { hw_id: entry.start_hw_id(), chunk_id: entry.start_chunk_id() };
+            if start.is_end() {
+                continue;
+            }
+            let addrs = chain_addrs(members, cipher, start).with_context(|| {
+                format!("generation {}: cannot follow {}", header.generation(), entry.name())
+            })?;
+            referenced.extend(addrs);
+        }
+    }
+
+    let mut dropped = BTreeSet::new();
+    let mut candidates = BTreeSet::new();
+    for index in load_committed(members)? {
+        if retained.contains(&index.generation) {
+            continue;
+        }
+        dropped.insert(index.generation);
+        let chunk = parse_index_chunk(&index.data)?;
+        for entry in chunk.entries().iter().flatten() {
+            let start = ChunkAddr { hw_id: entry.start_hw_id(), chunk_id: entry.start_chunk_id() };
+            if start.is_end() {
+                continue;
+            }
+            // A chain that no longer decrypts end to end may have been partly
+            // reused; guessing which of its chunks are free is not safe, so the
+            // whole plan is refused before anything is written.
+            let addrs = chain_addrs(members, cipher, start).with_context(|| {
+                format!("dropped generation {}: cannot follow {}", index.generation, entry.name())
+            })?;
+            candidates.extend(addrs);
+        }
+    }
+
+    Ok(TruncatePlan {
+        retained,
+        dropped: dropped.into_iter().collect(),
+        freed: candidates.difference(&referenced).copied().collect(),
+    })
+}
+
+/// Carries out `plan` against `head` and returns the new head: `head`'s entries
+/// committed again with the oldest retained generation as history floor.
+pub fn apply(members: &MemberMap, head: &LoadedIndex, plan: &TruncatePlan) -> Result<LoadedIndex> {
+    let generation = next_generation(head.generation);
+    // On a full ring the new head takes the oldest retained slot.
+    let floor = plan
+        .retained
+        .iter()
+        .rev()
+        .copied()
+        .find(|&g| g != generation)
+        .unwrap_or(head.generation);
+    let entries = parse_index_chunk(&head.data)?.unpack().entries.unwrap_or_default();
+    let committed = commit_with_floor(members, head, &entries, floor)?;
+
+    for dev in members.values() {
+        for &dropped in plan.dropped.iter().filter(|&&g| g != generation) {
+            clear_index_slot(dev.as_ref(), dropped)?;
+        }
+    }
+    for dev in members.values() {
+        dev.flush()?;
+    }
+
+    let blank = [0u8; CHUNK_SIZE];
+    for addr in &plan.freed {
+        let dev = members
+            .get(&addr.hw_id)
+            .with_context(|| format!("no member disk with hw_id {:#x}", addr.hw_id))?;
+        dev.write_chunk(addr.chunk_id, &blank)?;
+    }
+    for dev in members.values() {
+        dev.flush()?;
+    }
+    Ok(committed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block_device::FileBlockDevice;
+    use crate::data_chunk::write_chain;
+    use crate::index_store::{commit_next, load_latest};
+    use crate::layout::{min_device_chunks, read_index_slot, write_index_slot, DATA_START};
+    use crate::schemas::{build_minimal_index_chunk, verify_index_chain};
+    use std::collections::BTreeMap;
+    use tuff_crypto::chunk_cipher;
+    use tuff_schemas::tuff::tuff_os::FileEntryT;
+
+    fn entry(name: &str, chunk_id: u64) -> FileEntryT {
+        let mut e = FileEntryT::default();
+        e.name = name.to_string();
+        e.size = 10;
+        e.start_hw_id = 1;
+        e.start_chunk_id = chunk_id;
+        e
+    }
+
+    #[test]
+    fn truncate_drops_old_generations_and_frees_their_chunks() {
+        let path = std::env::temp_dir().join(format!("tuff_truncate_{}.img", std::process::id()));
+        let dev = FileBlockDevice::create(&path, min_device_chunks() + 4).unwrap();
+        write_index_slot(&dev, 1, &build_minimal_index_chunk("vol", 1).unwrap()).unwrap();
+        let mut map: MemberMap = BTreeMap::new();
+        map.insert(1, Box::new(dev));
+        let cipher = chunk_cipher::ChunkCipher::new(&[9u8; 32]);
+
+        let old = ChunkAddr { hw_id: 1, chunk_id: DATA_START };
+        let kept = ChunkAddr { hw_id: 1, chunk_id: DATA_START + 1 };
+        write_chain(&map, &cipher, &[old], b"old data..").unwrap();
+        write_chain(&map, &cipher, &[kept], b"kept data.").unwrap();
+
+        // gen 2 holds "a"; gen 3 replaces it with "b"; gen 4 keeps "b".
+        let g1 = load_latest(&map).unwrap().unwrap();
+        let g2 = commit_next(&map, &g1, &[entry("a", old.chunk_id)]).unwrap();
+        let g3 = commit_next(&map, &g2, &[entry("b", kept.chunk_id)]).unwrap();
+        let g4 = commit_next(&map, &g3, &[entry("b", kept.chunk_id)]).unwrap();
+
+        let by_count = Retention { keep: Some(2), max_age_secs: None };
+        let p = plan(&map, &cipher, &g4, &by_count, g4.timestamp).unwrap();
+        assert_eq!(p.retained, vec![4, 3]);
+        assert_eq!(p.dropped, vec![1, 2]);
+        assert_eq!(p.freed, vec![old]);
+
+        // Everything is young enough and within count: nothing to do.
+        let by_age = Retention { keep: None, max_age_secs: Some(3600) };
+        let p_age = plan(&map, &cipher, &g4, &by_age, g4.timestamp).unwrap();
+        assert_eq!(p_age.retained, vec![4, 3, 2, 1]);
+        assert!(p_age.dropped.is_empty() && p_age.freed.is_empty());
+
+        let g5 = apply(&map, &g4, &p).unwrap();
+        assert_eq!(g5.generation, 5);
+        assert!(read_index_slot(map[&1].as_ref(), 2).unwrap().is_none());
+        assert!(chain_addrs(&map, &cipher, old).is_err());
+        assert_eq!(chain_addrs(&map, &cipher, kept).unwrap(), vec![kept]);
+
+        // The walk stops at the recorded floor instead of the cleared slots.
+        let history = load_history(&map, &g5).unwrap();
+        let refs: Vec<&[u8]> = history.iter().map(|d| d.as_slice()).collect();
+        assert_eq!(refs.len(), 3);
+        verify_index_chain(&refs).unwrap();
+        assert_eq!(load_latest(&map).unwrap().unwrap().data, g5.data);
+        assert_eq!(parse_index_chunk(&g5.data).unwrap().unpack().entries.unwrap().len(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn broken_dropped_chain_aborts_the_plan() {
+        let path = std::env::temp_dir().join(format!("tuff_truncate_broken_{}.img", std::process::id()));
+        let dev = FileBlockDevice::create(&path, min_device_chunks() + 4).unwrap();
+        write_index_slot(&dev, 1, &build_minimal_index_chunk("vol", 1).unwrap()).unwrap();
+        let mut map: MemberMap = BTreeMap::new();
+        map.insert(1, Box::new(dev));
+        let cipher = chunk_cipher::ChunkCipher::new(&[9u8; 32]);
+
+        let old = ChunkAddr { hw_id: 1, chunk_id: DATA_START };
+        let kept = ChunkAddr { hw_id: 1, chunk_id: DATA_START + 1 };
+        write_chain(&map, &cipher, &[old], b"old data..").unwrap();
+        write_chain(&map, &cipher, &[kept], b"kept data.").unwrap();
+        let g1 = load_latest(&map).unwrap().unwrap();
+        let g2 = commit_next(&map, &g1, &[entry("a", old.chunk_id)]).unwrap();
+        let g3 = commit_next(&map, &g2, &[entry("b", kept.chunk_id)]).unwrap();
+
+        // The chunk only generation 2 referenced no longer decrypts.
+        map[&1].write_chunk(old.chunk_id, &[0xA5u8; CHUNK_SIZE]).unwrap();
+        let by_count = Retention { keep: Some(1), max_age_secs: None };
+        let err = plan(&map, &cipher, &g3, &by_count, g3.timestamp).unwrap_err();
+        assert!(format!("{:#}", err).contains("dropped generation 2"), "{:#}", err);
+
+        // Nothing was touched.
+        assert!(read_index_slot(map[&1].as_ref(), 2).unwrap().is_some());
+        assert_eq!(chain_addrs(&map, &cipher, kept).unwrap(), vec![kept]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result, bail};
+use dialoguer::{Confirm, theme::ColorfulTheme};
+use rand::RngCore;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tuff_common::block_device::{self, CHUNK_SIZE};
+use tuff_common::disk_detect;
+use tuff_common::layout::{self, INITIAL_CHUNK_INDEX, INDEX_RING_SLOTS};
+use tuff_common::schemas::{build_initial_chunk, build_minimal_index_chunk};
+use tuff_crypto::key_manager::mk_fingerprint;
+use tuff_verify::backup_token::Operations;
+
+use crate::install_guard;
+use crate::usb_storage::UsbKeyStore;
+
+/// Lays out a new TUFF-FS volume across `disks`.
+pub fn run_format(
+    disks: &[PathBuf],
+    volume_name: &str,
+    redundancy: u8,
+    allow_legacy_key: bool,
+) -> Result<()> {
+    println!("*** TUFF-FS FORMAT ***");
+
+    let unique: HashSet<PathBuf> = disks
+        .iter()
+        .map(|d| fs::canonicalize(d).unwrap_or_else(|_| d.clone()))
+        .collect();
+    if unique.len() != disks.len() {
+        bail!("The same disk was given more than once.");
+    }
+
+    // 1. Inspect every target before touching any of them.
+    let mut targets = Vec::new();
+    for disk in disks {
+        if let Some(why) = device_in_use(disk)? {
+            bail!("{:?} is in use ({}). Refusing to format.", disk, why);
+        }
+        let dev = block_device::open_device(disk)?;
+        if dev.chunk_count() < layout::min_device_chunks() {
+            bail!(
+                "{:?} is too small: {} chunks, need at least {}",
+                disk,
+                dev.chunk_count(),
+                layout::min_device_chunks()
+            );
+        }
+        let report = disk_detect::inspect(dev.as_ref())?;
+        if let Some(uuid) = report.tuff_volume() {
+            bail!("{:?} is already a member of TUFF-FS volume {}. Refusing to format.", disk, uuid);
+        }
+        targets.push((disk, dev, report));
+    }
+
+    // 2. Load the Master Key bound to this machine.
+    let sys_uuid = UsbKeyStore::get_system_uuid()?;
+    let usb = UsbKeyStore::select_usb_device("Select USB Device holding the Master Key")?;
+    let key = UsbKeyStore::read_key_from_usb(&usb, &sys_uuid, allow_legacy_key)?;
+    let fingerprint = mk_fingerprint(&key);
+
+    // Overwriting an existing OS or filesystem needs a signed token on the MK USB.
+    for (disk, _, report) in &targets {
+        if !report.is_blank() {
+            let what = if report.has_efi_system_partition() { "an installed OS" } else { "existing data" };
+            println!("{:?} holds {}: {}", disk, what, report);
+            install_guard::require_token(&usb, disk, Operations::INSTALL)?;
+        }
+    }
+
+    // 3. Last chance to back out.
+    println!("\nThe following disks will be ERASED and joined into volume '{}':", volume_name);
+    for (disk, dev, report) in &targets {
+        println!("  {:?} ({} MiB, {})", disk, dev.size() / (1024 * 1024), report);
+    }
+    let proceed = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("ALL DATA on these disks will be destroyed. Continue?")
+        .default(false)
+        .interact()?;
+    if !proceed {
+        println!("Format aborted. No disk was modified.");
+        return Ok(());
+    }
+
+    // 4. Write the index ring and anchors.
+    let volume_uuid = new_volume_uuid();
+    let index = build_minimal_index_chunk(volume_name, redundancy)?;
+    let mut hw_ids = HashSet::new();
+
+    for (disk, dev, _) in &targets {
+        let hw_id = loop {
+            let candidate = rand::thread_rng().next_u64();
+            if candidate != 0 && hw_ids.insert(candidate) {
+                break candidate;
+            }
+        };
+        let sector_size = physical_sector_size(disk);
+        let anchor = build_initial_chunk(&volume_uuid, hw_id, &fingerprint, sector_size)?;
+
+        // Empty every slot so stale data can never pass for a newer generation.
+        for generation in 1..=INDEX_RING_SLOTS as u8 {
+            layout::clear_index_slot(dev.as_ref(), generation)?;
+        }
+        layout::write_index_slot(dev.as_ref(), 1, &index)?;
+        dev.flush()?;
+
+        // The anchor goes last: a disk interrupted mid-format is not a member.
+        dev.write_chunk(INITIAL_CHUNK_INDEX, &anchor)?;
+        dev.flush()?;
+        println!("  {:?}: hw_id={:#018x} sector_size={}", disk, hw_id, sector_size);
+    }
+
+    println!("\n[SUCCESS] Volume '{}' created (UUID {}).", volume_name, volume_uuid);
+    Ok(())
+}
+
+/// Why `disk` must not be written to right now, if anything holds it: a mount
+/// or swap of the disk or one of its partitions, or a device-mapper, LVM or md
+/// device stacked on top of them.
+pub(crate) fn device_in_use(disk: &Path) -> Result<Option<String>> {
+    let resolved = fs::canonicalize(disk)
+        .with_context(|| format!("Cannot resolve {:?}", disk))?;
+    let mut sources = fs::read_to_string("/proc/mounts").unwrap_or_default();
+    sources.push_str(&fs::read_to_string("/proc/swaps").unwrap_or_default());
+
+    let sysfs = resolved
+        .file_name()
+        .map(|n| Path::new("/sys/class/block").join(n))
+        .filter(|p| resolved.starts_with("/dev") && p.exists());
+    let Some(sysfs) = sysfs else {
+        // Image file: only the file itself being mounted or swapped on counts.
+        let mounted = sources
+            .lines()
+            .filter_map(|l| l.split_whitespace().next())
+            .any(|src| Path::new(src) == resolved);
+        return Ok(mounted.then(|| format!("{:?} is mounted", resolved)));
+    };
+
+    let mut names = vec![sysfs.file_name().unwrap().to_string_lossy().to_string()];
+    for entry in fs::read_dir(&sysfs)?.flatten() {
+        if entry.path().join("partition").exists() {
+            names.push(entry.file_name().to_string_lossy().to_string());
+        }
+    }
+    for name in &names {
+        let holders = Path::new("/sys/class/block").join(name).join("holders");
+        if let Some(holder) = fs::read_dir(holders).ok().and_then(|mut d| d.next()).and_then(|e| e.ok()) {
+            return Ok(Some(format!("{} is held by {}", name, holder.file_name().to_string_lossy())));
+        }
+    }
+    Ok(mounted_device(&sources, &names).map(|name| format!("{} is mounted or used as swap", name)))
+}
+
+/// First of `names` (kernel device names) used as a source in a
+/// /proc/mounts or /proc/swaps listing. Sources such as /dev/disk/by-uuid links
+/// are resolved, and names compare exactly: sda is not sdaa1.
+fn mounted_device<'a>(sources: &str, names: &'a [String]) -> Option<&'a String> {
+    sources
+        .lines()
+        .filter_map(|l| l.split_whitespace().next())
+        .filter(|src| src.starts_with("/dev/"))
+        .filter_map(|src| {
+            let path = fs::canonicalize(src).unwrap_or_else(|_| PathBuf::from(src));
+            path.file_name().map(|n| n.to_string_lossy().to_string())
+        })
+        .find_map(|src| names.iter().find(|n| **n == src))
+}
+
+fn physical_sector_size(disk: &Path) -> u32 {
+    let name = fs::canonicalize(disk)
+        .ok()
+        .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()));
+    if let Some(name) = name {
+        let sysfs = format!("/sys/class/block/{}/queue/physical_block_size", name);
+        if let Ok(v) = fs::read_to_string(sysfs) {
+            if let Ok(size) = v.trim().parse::<u32>() {
+                // Anything outside 512..=4096 cannot be addressed per chunk; use the chunk size.
+                if size.is_power_of_two() && (512..=CHUNK_SIZE as u32).contains(&size) {
+                    return size;
+                }
+            }
+        }
+    }
+    // Image files and unknown devices: assume native 4K sectors.
+    CHUNK_SIZE as u32
+}
+
+fn new_volume_uuid() -> String {
+    let mut b = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut b);
+    // RFC 4122 version 4, variant 1
+    b[6] = (b[6] & 0x0f) | 0x40;
+    b[8] = (b[8] & 0x3f) | 0x80;
+    let h = hex::encode(b);
+    format!("{}-{}-{}-{}-{}", &h[0..8], &h[8..12], &h[12..16], &h[16..20], &h[20..32])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mount_sources_match_whole_device_names() {
+        let mounts = "/dev/sdaa1 / ext4 rw 0 0\n/dev/sdb10 /home ext4 rw 0 0\nproc /proc proc rw 0 0\n";
+        let disk = |names: &[&str]| names.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(mounted_device(mounts, &disk(&["sda", "sda1"])), None);
+        assert_eq!(mounted_device(mounts, &disk(&["sdb1"])), None);
+        assert_eq!(mounted_device(mounts, &disk(&["sdb", "sdb1", "sdb10"])).map(String::as_str), Some("sdb10"));
+        let swaps = "Filename Type Size Used Priority\n/dev/sdc2 partition 1024 0 -2\n";
+        assert_eq!(mounted_device(swaps, &disk(&["sdc", "sdc2"])).map(String::as_str), Some("sdc2"));
+    }
+}
@@ -0,0 +1,88 @@
+// The printed form of the Master Key: 4 lines of 4 hex groups, each line
+// followed by a check group so a mistyped line is caught when it is entered.
+use anyhow::{bail, Result};
+use sha2::{Digest, Sha256};
+
+pub const LINES: usize = 4;
+pub const GROUPS_PER_LINE: usize = 4;
+/// Key bytes per line (4 groups of 4 hex digits).
+pub const LINE_BYTES: usize = 8;
+
+/// Check group for `line` (1-based): 4 hex digits of SHA-256 over the line
+/// number and its bytes, so swapped lines are caught too.
+pub fn line_checksum(line: usize, bytes: &[u8]) -> String {
+    let digest = Sha256::new()
+        .chain_update(b"TUFF-GRID")
+        .chain_update([line as u8])
+        .chain_update(bytes)
+        .finalize();
+    hex::encode_upper(&digest[..2])
+}
+
+/// Renders `key` as `LINES` lines of `(groups, check group)`.
+pub fn render(key: &[u8; 32]) -> Vec<(Vec<String>, String)> {
+    key.chunks(LINE_BYTES)
+        .enumerate()
+        .map(|(i, bytes)| {
+            let groups = bytes.chunks(2).map(hex::encode_upper).collect();
+            (groups, line_checksum(i + 1, bytes))
+        })
+        .collect()
+}
+
+/// Parses one typed line (1-based). Whitespace is ignored. The check group
+/// is optional, for grids printed before it existed, but must match if given.
+pub fn parse_line(line: usize, input: &str) -> Result<[u8; LINE_BYTES]> {
+    let digits: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    // Checked before measuring or slicing: both count bytes, not characters.
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("Not valid hex");
+    }
+    let (data, check) = match digits.len() {
+        16 => (&digits[..], None),
+        20 => (&digits[..16], Some(&digits[16..])),
+        n => bail!("Expected {} groups of 4 hex digits (plus the check group), got {} digits", GROUPS_PER_LINE, n),
+    };
+    let mut bytes = [0u8; LINE_BYTES];
+    hex::decode_to_slice(data, &mut bytes).map_err(|_| anyhow::anyhow!("Not valid hex"))?;
+    if let Some(check) = check {
+        if !check.eq_ignore_ascii_case(&line_checksum(line, &bytes)) {
+            bail!("Check group does not match; re-read line {}", line);
+        }
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendered_lines_parse_back() {
+        let key: [u8; 32] = core::array::from_fn(|i| (i * 7) as u8);
+        for (i, (groups, check)) in render(&key).iter().enumerate() {
+            let typed = format!("{} {}", groups.join(" ").to_lowercase(), check);
+            assert_eq!(parse_line(i + 1, &typed).unwrap()[..], key[i * 8..(i + 1) * 8]);
+            // Without the check group the line is still accepted.
+            assert!(parse_line(i + 1, &groups.join("")).is_ok());
+        }
+    }
+
+    #[test]
+    fn typos_and_swapped_lines_are_caught() {
+        let key = [0xABu8; 32];
+        let lines = render(&key);
+        let (groups, check) = &lines[0];
+        let mut typo = groups.join(" ");
+        typo.replace_range(0..1, "B");
+        assert!(parse_line(1, &format!("{} {}", typo, check)).is_err());
+        // Identical bytes on another line carry a different check group.
+        assert!(parse_line(2, &format!("{} {}", groups.join(" "), check)).is_err());
+        assert!(parse_line(1, "ABAB ABAB ABAB").is_err());
+        assert!(parse_line(1, "ZZZZ ABAB ABAB ABAB").is_err());
+        // Multi-byte characters are refused as hex, not sliced through.
+        let err = parse_line(1, "ABAB ABAB ABAB ABAé 123").unwrap_err();
+        assert!(err.to_string().contains("Not valid hex"));
+        assert!(parse_line(1, "ＡＢＡＢ ABAB ABAB ABAB").is_err());
+    }
+}
@@ -0,0 +1,364 @@
+// Block-image backup and restore: the only supported way to write a disk back.
+//
+// `<image>` is a raw, sparse copy of the device; `<image>.manifest` records its
+// size and a SHA-256 per 4 MiB extent (none for all-zero extents, which are
+// left as holes). Restore checks the whole image against the manifest before
+// the first write and every extent again as it writes it, and records its
+// progress in `<image>.restore` so an interrupted restore picks up at the next
+// unwritten extent.
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tuff_common::block_device;
+use tuff_common::disk_detect;
+use tuff_verify::backup_token::Operations;
+
+use crate::install_guard;
+use crate::usb_storage::UsbKeyStore;
+
+pub const EXTENT_SIZE: u64 = 4 * 1024 * 1024;
+const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// Device the image was taken from.
+    pub source: String,
+    pub size: u64,
+    pub extent_size: u64,
+    pub created_at: i64,
+    /// Hex SHA-256 per extent; `None` for an all-zero extent.
+    pub extents: Vec<Option<String>>,
+}
+
+/// Restore position, rewritten after every extent reaches the target.
+#[derive(Debug, Serialize, Deserialize)]
+struct Progress {
+    target: String,
+    manifest_sha256: String,
+    next_extent: usize,
+}
+
+pub fn manifest_path(image: &Path) -> PathBuf {
+    with_suffix(image, "manifest")
+}
+
+fn progress_path(image: &Path) -> PathBuf {
+    with_suffix(image, "restore")
+}
+
+/// `tuffctl backup`: images `source` and writes the manifest. Reading a disk
+/// always needs a backup token granting `backup` on the MK USB.
+pub fn run_backup(source: &Path, image: &Path) -> Result<()> {
+    println!("*** TUFF BLOCK-IMAGE BACKUP ***");
+    let usb = UsbKeyStore::select_usb_device("Select USB Device holding the backup token")?;
+    install_guard::require_token(&usb, source, Operations::BACKUP)?;
+    let manifest = backup(source, image)?;
+    let stored = manifest.extents.iter().filter(|e| e.is_some()).count();
+    println!(
+        "[SUCCESS] {:?} -> {:?}: {} MiB, {} of {} extents stored, manifest {:?}",
+        source,
+        image,
+        manifest.size / (1024 * 1024),
+        stored,
+        manifest.extents.len(),
+        manifest_path(image)
+    );
+    Ok(())
+}
+
+/// `tuffctl restore`: writes an image back after verifying it. A target that
+/// is not blank needs a backup token granting `restore` on the MK USB.
+pub fn run_restore(image: &Path, target: &Path) -> Result<()> {
+    println!("*** TUFF BLOCK-IMAGE RESTORE ***");
+    if let Some(why) = crate::format::device_in_use(target)? {
+        bail!("{:?} is in use ({}). Refusing to restore.", target, why);
+    }
+    // Also applies when resuming: the progress file sits next to the image and
+    // proves nothing about who started the restore.
+    let report = disk_detect::inspect(block_device::open_device(target)?.as_ref())?;
+    if !report.is_blank() {
+        println!("{:?} holds existing data: {}", target, report);
+        let usb = UsbKeyStore::select_usb_device("Select USB Device holding the backup token")?;
+        install_guard::require_token(&usb, target, Operations::RESTORE)?;
+    }
+    let resumed_from = restore(image, target)?;
+    if resumed_from > 0 {
+        println!("Resumed at extent {}.", resumed_from);
+    }
+    println!("[SUCCESS] {:?} restored to {:?}.", image, target);
+    Ok(())
+}
+
+/// Streams `source` into a sparse image at `image` and writes its manifest.
+pub fn backup(source: &Path, image: &Path) -> Result<Manifest> {
+    let mut src = File::open(source).with_context(|| format!("Cannot open {:?}", source))?;
+    let size = src.seek(SeekFrom::End(0))?;
+    let out = File::create(image).with_context(|| format!("Cannot create {:?}", image))?;
+    // Zero extents are never written, so they stay holes.
+    out.set_len(size)?;
+
+    let mut extents = Vec::new();
+    let mut buf = vec![0u8; EXTENT_SIZE as usize];
+    let mut offset = 0;
+    while offset < size {
+        let len = EXTENT_SIZE.min(size - offset) as usize;
+        src.read_exact_at(&mut buf[..len], offset)
+            .with_context(|| format!("Read failed at byte {} of {:?}", offset, source))?;
+        let extent = &buf[..len];
+        if extent.iter().all(|&b| b == 0) {
+            extents.push(None);
+        } else {
+            out.write_all_at(extent, offset)?;
+            extents.push(Some(hex::encode(Sha256::digest(extent))));
+        }
+        offset += len as u64;
+    }
+    out.sync_all()?;
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        source: source.to_string_lossy().to_string(),
+        size,
+        extent_size: EXTENT_SIZE,
+        created_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+        extents,
+    };
+    write_synced(&manifest_path(image), &serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(manifest)
+}
+
+/// Checks every extent of `image` against `manifest`.
+pub fn verify(image: &Path, manifest: &Manifest) -> Result<()> {
+    if manifest.version != MANIFEST_VERSION {
+        bail!("Unsupported manifest version {}", manifest.version);
+    }
+    // The manifest is not authenticated; buffers are sized by this build's
+    // extent size, never by the file.
+    if manifest.extent_size != EXTENT_SIZE {
+        bail!("Manifest extent size {} is not {}", manifest.extent_size, EXTENT_SIZE);
+    }
+    if manifest.extents.len() as u64 != manifest.size.div_ceil(manifest.extent_size) {
+        bail!("Manifest extent list does not cover {} bytes", manifest.size);
+    }
+    let file = File::open(image).with_context(|| format!("Cannot open {:?}", image))?;
+    let image_size = file.metadata()?.len();
+    if image_size != manifest.size {
+        bail!("Image is {} bytes, manifest says {}", image_size, manifest.size);
+    }
+    let mut buf = vec![0u8; manifest.extent_size as usize];
+    for (i, expected) in manifest.extents.iter().enumerate() {
+        let extent = read_extent(&file, manifest, i, &mut buf)?;
+        if !extent_matches(extent, expected) {
+            bail!("Extent {} of {:?} does not match the manifest", i, image);
+        }
+    }
+    Ok(())
+}
+
+fn extent_matches(extent: &[u8], expected: &Option<String>) -> bool {
+    match expected {
+        Some(hash) => hex::encode(Sha256::digest(extent)) == *hash,
+        None => extent.iter().all(|&b| b == 0),
+    }
+}
+
+
+/// Verifies `image`, then writes it to `target` extent by extent. Returns
+/// the extent the restore resumed at (0 for a fresh restore).
+pub fn restore(image: &Path, target: &Path) -> Result<usize> {
+    let manifest_bytes = fs::read(manifest_path(image))
+        .with_context(|| format!("Cannot read {:?}", manifest_path(image)))?;
+    let manifest: Manifest = serde_json::from_slice(&manifest_bytes).context("Malformed manifest")?;
+    let manifest_sha256 = hex::encode(Sha256::digest(&manifest_bytes));
+    verify(image, &manifest)?;
+
+    let target_name = fs::canonicalize(target)
+        .with_context(|| format!("Cannot resolve {:?}", target))?
+        .to_string_lossy()
+        .to_string();
+    let mut out = OpenOptions::new()
+        .write(true)
+        .open(target)
+        .with_context(|| format!("Cannot open {:?} for writing", target))?;
+    let target_size = out.seek(SeekFrom::End(0))?;
+    if target_size < manifest.size {
+        bail!("{:?} is {} bytes, the image needs {}", target, target_size, manifest.size);
+    }
+
+    let progress_file = progress_path(image);
+    let start = match fs::read(&progress_file) {
+        Ok(data) => {
+            let progress: Progress = serde_json::from_slice(&data).context("Malformed restore progress")?;
+            if progress.target != target_name || progress.manifest_sha256 != manifest_sha256 {
+                bail!(
+                    "{:?} records an unfinished restore of another image or target; remove it to start over",
+                    progress_file
+                );
+            }
+            progress.next_extent
+        }
+        Err(_) => 0,
+    };
+
+    let src = File::open(image)?;
+    let mut buf = vec![0u8; manifest.extent_size as usize];
+    for i in start..manifest.extents.len() {
+        let extent = read_extent(&src, &manifest, i, &mut buf)?;
+        // The image may have changed since `verify`.
+        if !extent_matches(extent, &manifest.extents[i]) {
+            bail!("Extent {} of {:?} changed after verification; restore stopped", i, image);
+        }
+        out.write_all_at(extent, i as u64 * manifest.extent_size)
+            .with_context(|| format!("Write failed at extent {} of {:?}", i, target))?;
+        out.sync_data()?;
+        let progress = Progress {
+            target: target_name.clone(),
+            manifest_sha256: manifest_sha256.clone(),
+            next_extent: i + 1,
+        };
+        write_synced(&progress_file, &serde_json::to_vec(&progress)?)?;
+    }
+    fs::remove_file(&progress_file)?;
+    Ok(start)
+}
+
+fn read_extent<'a>(file: &File, manifest: &Manifest, index: usize, buf: &'a mut [u8]) -> Result<&'a [u8]> {
+    let offset = index as u64 * manifest.extent_size;
+    let len = manifest.extent_size.min(manifest.size - offset) as usize;
+    file.read_exact_at(&mut buf[..len], offset)?;
+    Ok(&buf[..len])
+}
+
+/// Replaces `path` with `data` atomically (tmp + fsync + rename).
+fn write_synced(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = with_suffix(path, "tmp");
+    let file = File::create(&tmp)?;
+    file.write_all_at(data, 0)?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scratch(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("tuff_image_{}_{}", name, std::process::id()))
+    }
+
+    /// 2.5 extents: data, zeros, a short data tail.
+    fn source(name: &str) -> PathBuf {
+        let path = scratch(name);
+        let file = File::create(&path).unwrap();
+        file.set_len(EXTENT_SIZE * 5 / 2).unwrap();
+        file.write_all_at(b"first extent", 17).unwrap();
+        file.write_all_at(b"tail", EXTENT_SIZE * 2 + 100).unwrap();
+        path
+    }
+
+    fn cleanup(paths: &[PathBuf]) {
+        for p in paths {
+            for extra in [p.clone(), manifest_path(p), progress_path(p)] {
+                let _ = fs::remove_file(extra);
+            }
+        }
+    }
+
+    #[test]
+    fn backup_skips_zero_extents_and_restores() {
+        let (src, image, target) = (source("rt_src"), scratch("rt_img"), scratch("rt_dst"));
+        let manifest = backup(&src, &image).unwrap();
+        assert_eq!(manifest.extents.len(), 3);
+        assert!(manifest.extents[0].is_some() && manifest.extents[1].is_none() && manifest.extents[2].is_some());
+
+        let dst = File::create(&target).unwrap();
+        dst.set_len(manifest.size).unwrap();
+        dst.write_all_at(&[0xFF; 64], EXTENT_SIZE + 5).unwrap();
+        assert_eq!(restore(&image, &target).unwrap(), 0);
+        assert_eq!(fs::read(&target).unwrap(), fs::read(&src).unwrap());
+        assert!(!progress_path(&image).exists());
+        cleanup(&[src, image, target]);
+    }
+
+    #[test]
+    fn tampered_image_is_refused_before_writing() {
+        let (src, image, target) = (source("bad_src"), scratch("bad_img"), scratch("bad_dst"));
+        let manifest = backup(&src, &image).unwrap();
+        File::options().write(true).open(&image).unwrap().write_all_at(b"X", EXTENT_SIZE * 2 + 1).unwrap();
+
+        let dst = File::create(&target).unwrap();
+        dst.set_len(manifest.size).unwrap();
+        assert!(restore(&image, &target).is_err());
+        assert!(fs::read(&target).unwrap().iter().all(|&b| b == 0));
+        cleanup(&[src, image, target]);
+    }
+
+    #[test]
+    fn interrupted_restore_resumes() {
+        let (src, image, target) = (source("resume_src"), scratch("resume_img"), scratch("resume_dst"));
+        let manifest = backup(&src, &image).unwrap();
+        let dst = File::create(&target).unwrap();
+        dst.set_len(manifest.size).unwrap();
+
+        // As if the restore died after writing extents 0 and 1.
+        let manifest_sha256 = hex::encode(Sha256::digest(fs::read(manifest_path(&image)).unwrap()));
+        let progress = Progress {
+            target: fs::canonicalize(&target).unwrap().to_string_lossy().to_string(),
+            manifest_sha256,
+            next_extent: 2,
+        };
+        fs::write(progress_path(&image), serde_json::to_vec(&progress).unwrap()).unwrap();
+        let head = fs::read(&src).unwrap()[..EXTENT_SIZE as usize].to_vec();
+        dst.write_all_at(&head, 0).unwrap();
+
+        assert_eq!(restore(&image, &target).unwrap(), 2);
+        assert_eq!(fs::read(&target).unwrap(), fs::read(&src).unwrap());
+        cleanup(&[src, image, target]);
+    }
+
+    #[test]
+    fn foreign_extent_size_is_refused() {
+        let (src, image, target) = (source("size_src"), scratch("size_img"), scratch("size_dst"));
+        let mut manifest = backup(&src, &image).unwrap();
+        manifest.extent_size = 512;
+        manifest.extents = vec![None; manifest.size.div_ceil(512) as usize];
+        fs::write(manifest_path(&image), serde_json::to_vec(&manifest).unwrap()).unwrap();
+
+        let dst = File::create(&target).unwrap();
+        dst.set_len(manifest.size).unwrap();
+        let err = restore(&image, &target).unwrap_err();
+        assert!(err.to_string().contains("extent size"), "{:#}", err);
+        assert!(fs::read(&target).unwrap().iter().all(|&b| b == 0));
+        cleanup(&[src, image, target]);
+    }
+
+    #[test]
+    fn extent_changed_after_verify_stops_the_restore() {
+        let (src, image) = (source("race_src"), scratch("race_img"));
+        let manifest = backup(&src, &image).unwrap();
+        let mut buf = vec![0u8; EXTENT_SIZE as usize];
+        let file = File::open(&image).unwrap();
+        assert!(extent_matches(read_extent(&file, &manifest, 2, &mut buf).unwrap(), &manifest.extents[2]));
+
+        File::options().write(true).open(&image).unwrap().write_all_at(b"X", EXTENT_SIZE * 2 + 1).unwrap();
+        assert!(!extent_matches(read_extent(&file, &manifest, 2, &mut buf).unwrap(), &manifest.extents[2]));
+        // A zero extent that gains data no longer matches either.
+        File::options().write(true).open(&image).unwrap().write_all_at(b"X", EXTENT_SIZE + 1).unwrap();
+        assert!(!extent_matches(read_extent(&file, &manifest, 1, &mut buf).unwrap(), &manifest.extents[1]));
+        cleanup(&[src, image]);
+    }
+}
@@ -0,0 +1,46 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tuff_verify::backup_token::{self, Operations, TOKEN_FILE_NAME};
+
+use crate::usb_storage::UsbKeyStore;
+
+/// Refuses unless the USB stick at `usb` carries a `TUFF_BACKUP_TOKEN.sig`
+/// signed with the embedded key that grants `operation` on `disk`.
+pub fn require_token(usb: &Path, disk: &Path, operation: Operations) -> Result<()> {
+    let serial = disk_serial(disk)?;
+    let Some(data) = UsbKeyStore::read_root_file(usb, TOKEN_FILE_NAME)? else {
+        bail!("{} is missing from the USB root; {:?} needs one for this operation", TOKEN_FILE_NAME, disk);
+    };
+    let public_key = backup_token::embedded_public_key()
+        .map_err(|_| anyhow::anyhow!("This tuffctl build has no token verification key"))?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).context("time went backwards")?.as_secs() as i64;
+    let token = backup_token::authorize(&data, &public_key, &serial, operation, now)
+        .map_err(|e| anyhow::anyhow!("{} rejected for {:?} (serial {}): {:?}", TOKEN_FILE_NAME, disk, serial, e))?;
+    println!("  {:?}: authorized by {} (serial {}, expires {})", disk, TOKEN_FILE_NAME, serial, token.expires_at);
+    Ok(())
+}
+
+/// Serial number of the disk holding `path` (its parent disk for a partition).
+pub fn disk_serial(path: &Path) -> Result<String> {
+    let resolved = fs::canonicalize(path).with_context(|| format!("Cannot resolve {:?}", path))?;
+    let name = resolved
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .context("Not a device path")?;
+    let mut sys = fs::canonicalize(Path::new("/sys/class/block").join(&name))
+        .with_context(|| format!("{:?} is not a block device; it has no serial number", path))?;
+    if sys.join("partition").exists() {
+        sys.pop();
+    }
+    for attr in ["device/serial", "serial", "device/wwid"] {
+        if let Ok(v) = fs::read_to_string(sys.join(attr)) {
+            let v = v.trim();
+            if !v.is_empty() {
+                return Ok(v.to_string());
+            }
+        }
+    }
+    bail!("Cannot read a serial number for {:?}", path)
+}
@@ -1,14 +1,27 @@
 use clap::{Parser, Subcommand};
-use anyhow::{Result, bail};
+use anyhow::{Context, Result, bail};
+use dialoguer::{Password, theme::ColorfulTheme};
 use rand::RngCore;
+use std::fs;
 use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tuff_common::control::{self, Request, Response};
+use zeroize::Zeroizing;
 
+mod format;
+mod hex_grid;
+mod image;
+mod install_guard;
+mod recover;
 mod usb_storage;
 
 #[derive(Parser)]
 #[command(name = "tuffctl")]
 #[command(about = "TUFF-OS Management CLI")]
 struct Cli {
+    /// Accept unwrapped 32-byte key files written by older releases
+    #[arg(long, global = true)]
+    allow_legacy_key: bool,
     #[command(subcommand)]
     command: Commands,
 }
@@ -16,15 +29,88 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     Init,
+    /// Create a new TUFF-FS volume on one or more disks
+    Format {
+        /// Target disks or image files (e.g. /dev/sdb)
+        #[arg(required = true)]
+        disks: Vec<PathBuf>,
+        #[arg(long, default_value = "tuff-volume")]
+        volume_name: String,
+        /// Default number of replicas per chunk (1=Single, 2=Mirror, 3=3N)
+        #[arg(long, default_value_t = 1)]
+        redundancy: u8,
+    },
+    /// Show the state of the running tuffd
+    Status,
+    /// Leave Freeze by presenting the Master Key to tuffd
+    Unfreeze {
+        /// Why the freeze is being lifted (recorded in the audit log)
+        #[arg(long)]
+        reason: String,
+        /// Read the key from the USB key stick instead of typing its hex
+        #[arg(long)]
+        usb: bool,
+    },
+    /// Rebuild the Master Key from its printed hex grid and write a new key file
+    RecoverKey {
+        /// Check the key against this volume member instead of the fingerprint stored on this machine
+        #[arg(long)]
+        disk: Option<PathBuf>,
+    },
+    /// Copy a whole disk or partition to a sparse block image with a manifest
+    Backup {
+        /// Device to image (e.g. /dev/sda)
+        source: PathBuf,
+        /// Image file to create; the manifest is written next to it
+        image: PathBuf,
+    },
+    /// Verify a block image against its manifest and write it back
+    Restore {
+        image: PathBuf,
+        /// Device to overwrite
+        target: PathBuf,
+    },
+    /// Write a file to the open volume; it is published by the next commit
+    Put {
+        /// Local file to copy
+        file: PathBuf,
+        /// Name in the volume index (defaults to the file name)
+        #[arg(long)]
+        name: Option<String>,
+    },
+    /// Ask tuffd to publish a new committed IndexChunk generation
     Commit,
-    Truncate,
+    /// Drop old index generations and free the chunks only they referenced
+    Truncate {
+        /// Keep at most this many generations (the current one always stays)
+        #[arg(long)]
+        keep: Option<usize>,
+        /// Drop generations older than this many days
+        #[arg(long)]
+        max_age_days: Option<u32>,
+        /// Only print what would be dropped and freed
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
     match &cli.command {
         Commands::Init => run_init()?,
-        _ => println!("Not implemented yet"),
+        Commands::Format { disks, volume_name, redundancy } => {
+            format::run_format(disks, volume_name, *redundancy, cli.allow_legacy_key)?
+        }
+        Commands::Status => run_status()?,
+        Commands::Unfreeze { reason, usb } => run_unfreeze(reason, *usb, cli.allow_legacy_key)?,
+        Commands::RecoverKey { disk } => recover::run_recover_key(disk.as_deref())?,
+        Commands::Backup { source, image } => image::run_backup(source, image)?,
+        Commands::Restore { image, target } => image::run_restore(image, target)?,
+        Commands::Put { file, name } => run_put(file, name.as_deref())?,
+        Commands::Commit => run_commit()?,
+        Commands::Truncate { keep, max_age_days, dry_run } => {
+            run_truncate(*keep, *max_age_days, *dry_run)?
+        }
     }
     Ok(())
 }
@@ -58,12 +144,14 @@ fn run_init() -> Result<()> {
     println!("          TAKE A PHOTO OF THIS SCREEN NOW.");
     println!("================================================================\n");
 
-    let chunks = key_hex.as_bytes().chunks(4);
-    for (i, chunk) in chunks.enumerate() {
-        if i % 4 == 0 { print!("Line {}:  ", (i/4)+1); }
-        print!("{}     ", std::str::from_utf8(chunk).unwrap());
-        if (i+1) % 4 == 0 { println!("\n"); }
+    for (i, (groups, check)) in hex_grid::render(&key).iter().enumerate() {
+        print!("Line {}:  ", i + 1);
+        for group in groups {
+            print!("{}     ", group);
+        }
+        println!("[{}]\n", check);
     }
+    println!(" [....] is a check group, used by `tuffctl recover-key`.");
     println!("================================================================\n");
 
     // 3. Verification Logic (Corner Check)
@@ -86,16 +174,137 @@ fn run_init() -> Result<()> {
 
     // 4. USB Selection & Write
     println!("Scanning for USB devices...");
-    let target_usb = usb_storage::UsbKeyStore::select_usb_device()?;
+    let target_usb = usb_storage::UsbKeyStore::select_usb_device("Select USB Device to store the Master Key")?;
 
     println!("Writing key to {:?} for UUID {}...", target_usb, sys_uuid);
     usb_storage::UsbKeyStore::write_key_to_usb(&target_usb, &key, &sys_uuid)?;
 
-    println!("[SUCCESS] Key saved to USB. Ready to format TUFF-FS volumes (TODO).");
+    println!("[SUCCESS] Key saved to USB. Run `tuffctl format <disk>...` to create a volume.");
 
     Ok(())
 }
 
+fn run_status() -> Result<()> {
+    match control::call(&Request::Status)? {
+        Response::Status { version, state, key_loaded, index, volumes } => {
+            println!("tuffd {}", version);
+            println!("  state      : {:?}", state);
+            println!("  master key : {}", if key_loaded { "loaded" } else { "absent" });
+            match index {
+                Some(i) => println!(
+                    "  index      : generation {} at {} ({} replica(s))",
+                    i.generation,
+                    i.timestamp,
+                    i.replicas.len()
+                ),
+                None => println!("  index      : none"),
+            }
+            if volumes.is_empty() {
+                println!("  volumes    : none attached");
+            }
+            for v in &volumes {
+                println!("  volume {}{}", v.volume_uuid, if v.open { " [open]" } else { "" });
+                for m in &v.members {
+                    println!("    {} hw_id={:#018x}", m.device, m.hw_id);
+                }
+            }
+            Ok(())
+        }
+        Response::Error { message } => bail!("Status failed: {}", message),
+        other => bail!("Unexpected response from tuffd: {:?}", other),
+    }
+}
+
+fn run_unfreeze(reason: &str, usb: bool, allow_legacy_key: bool) -> Result<()> {
+    let master_key = if usb {
+        let sys_uuid = usb_storage::UsbKeyStore::get_system_uuid()?;
+        let device = usb_storage::UsbKeyStore::select_usb_device("Select USB Device holding the Master Key")?;
+        Zeroizing::new(usb_storage::UsbKeyStore::read_key_from_usb(&device, &sys_uuid, allow_legacy_key)?.to_vec())
+    } else {
+        let typed = Zeroizing::new(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Master Key (64 hex digits, spaces allowed)")
+                .interact()?,
+        );
+        let hex_digits: Zeroizing<String> =
+            Zeroizing::new(typed.chars().filter(|c| !c.is_whitespace()).collect());
+        let key = Zeroizing::new(hex::decode(hex_digits.as_str()).context("Key is not valid hex")?);
+        if key.len() != 32 {
+            bail!("Key must be 32 bytes (64 hex digits), got {}", key.len());
+        }
+        key
+    };
+
+    let request = Request::Unfreeze { master_key, reason: reason.to_string() };
+    match control::call(&request)? {
+        Response::Unfrozen { volume_uuid, generation } => {
+            println!("[SUCCESS] tuffd left Freeze. Volume {} at generation {}.", volume_uuid, generation);
+            Ok(())
+        }
+        Response::Error { message } => bail!("Unfreeze refused: {}", message),
+        other => bail!("Unexpected response from tuffd: {:?}", other),
+    }
+}
+
+fn run_put(file: &Path, name: Option<&str>) -> Result<()> {
+    let name = match name {
+        Some(name) => name.to_string(),
+        None => file
+            .file_name()
+            .and_then(|n| n.to_str())
+            .with_context(|| format!("Cannot take an index name from {:?}; pass --name", file))?
+            .to_string(),
+    };
+    let data = fs::read(file).with_context(|| format!("Failed to read {:?}", file))?;
+    match control::call(&Request::Put { name, data })? {
+        Response::Staged { name, size, queue_depth } => {
+            println!("[SUCCESS] Staged {} ({} bytes); run `tuffctl commit` to publish it.", name, size);
+            println!("  queued chunks : {}", queue_depth);
+            Ok(())
+        }
+        Response::Error { message } => bail!("Put refused: {}", message),
+        other => bail!("Unexpected response from tuffd: {:?}", other),
+    }
+}
+
+fn run_commit() -> Result<()> {
+    match control::call(&Request::Commit)? {
+        Response::Committed { generation, timestamp, entries } => {
+            println!("[SUCCESS] Committed generation {}", generation);
+            println!("  timestamp : {}", timestamp);
+            println!("  entries   : {}", entries);
+            Ok(())
+        }
+        Response::Error { message } => bail!("Commit refused: {}", message),
+        other => bail!("Unexpected response from tuffd: {:?}", other),
+    }
+}
+
+fn run_truncate(keep: Option<usize>, max_age_days: Option<u32>, dry_run: bool) -> Result<()> {
+    if keep.is_none() && max_age_days.is_none() {
+        bail!("Give --keep and/or --max-age-days");
+    }
+    let request = Request::Truncate {
+        keep,
+        max_age_secs: max_age_days.map(|d| d as i64 * 86_400),
+        dry_run,
+    };
+    match control::call(&request)? {
+        Response::Truncated { retained, dropped, freed, dry_run } => {
+            let verb = if dry_run { "Would drop" } else { "Dropped" };
+            println!("Retained generations : {:?}", retained);
+            println!("{} generations : {:?}", verb, dropped);
+            println!("{} {} data chunk(s):", if dry_run { "Would free" } else { "Freed" }, freed.len());
+            for addr in &freed {
+                println!("  hw_id={:#018x} chunk={}", addr.hw_id, addr.chunk_id);
+            }
+            Ok(())
+        }
+        Response::Error { message } => bail!("Truncate refused: {}", message),
+        other => bail!("Unexpected response from tuffd: {:?}", other),
+    }
+}
+
 fn prompt(msg: &str) -> Result<String> {
     print!("{}", msg);
     io::stdout().flush()?;
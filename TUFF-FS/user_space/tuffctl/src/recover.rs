@@ -0,0 +1,71 @@
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::Path;
+use tuff_common::block_device::{self, CHUNK_SIZE};
+use tuff_common::layout::INITIAL_CHUNK_INDEX;
+use tuff_common::paths::MK_FINGERPRINT_PATH;
+use tuff_common::schemas::{parse_initial_chunk, validate_initial_chunk};
+use tuff_crypto::key_manager::{mk_fingerprint, FINGERPRINT_LEN};
+use zeroize::Zeroizing;
+
+use crate::hex_grid::{self, LINES, LINE_BYTES};
+use crate::usb_storage::UsbKeyStore;
+
+/// Rebuilds the Master Key from the photographed hex grid and writes a fresh
+/// key file. The key is checked against the anchor of `disk` when given,
+/// otherwise against the fingerprint tuffd recorded on this machine.
+pub fn run_recover_key(disk: Option<&Path>) -> Result<()> {
+    println!("*** TUFF-FS MASTER KEY RECOVERY ***");
+
+    let sys_uuid = UsbKeyStore::get_system_uuid()?;
+    let expected = expected_fingerprint(disk)?;
+
+    println!("Enter each line of the key grid: 4 groups, then the check group if printed.\n");
+    let mut key = Zeroizing::new([0u8; 32]);
+    for line in 1..=LINES {
+        loop {
+            let typed = Zeroizing::new(crate::prompt(&format!("Line {}: ", line))?);
+            match hex_grid::parse_line(line, &typed) {
+                Ok(bytes) => {
+                    key[(line - 1) * LINE_BYTES..line * LINE_BYTES].copy_from_slice(&bytes);
+                    println!("        check group {}", hex_grid::line_checksum(line, &bytes));
+                    break;
+                }
+                Err(e) => println!("  [ERROR] {}", e),
+            }
+        }
+    }
+
+    if mk_fingerprint(key.as_slice()) != expected {
+        bail!("The entered key does not match the Master Key fingerprint. Nothing was written.");
+    }
+    println!("\n[SUCCESS] Key matches the Master Key fingerprint.");
+
+    let usb = UsbKeyStore::select_usb_device("Select USB Device to store the recovered Master Key")?;
+    println!("Writing key to {:?} for UUID {}...", usb, sys_uuid);
+    UsbKeyStore::write_key_to_usb(&usb, &key, &sys_uuid)?;
+    println!("[SUCCESS] Recovered key saved to USB.");
+    Ok(())
+}
+
+fn expected_fingerprint(disk: Option<&Path>) -> Result<[u8; FINGERPRINT_LEN]> {
+    let found = match disk {
+        Some(disk) => {
+            let dev = block_device::open_device(disk)?;
+            let mut anchor = vec![0u8; CHUNK_SIZE];
+            dev.read_chunk(INITIAL_CHUNK_INDEX, &mut anchor)?;
+            validate_initial_chunk(&anchor)
+                .with_context(|| format!("{:?} is not a TUFF-FS member", disk))?;
+            parse_initial_chunk(&anchor)?.mk_fingerprint().unwrap_or_default().to_vec()
+        }
+        None => {
+            let text = fs::read_to_string(MK_FINGERPRINT_PATH).with_context(|| {
+                format!("Cannot read {}; pass --disk with a volume member instead", MK_FINGERPRINT_PATH)
+            })?;
+            hex::decode(text.trim()).context("Stored MK fingerprint is not valid hex")?
+        }
+    };
+    found
+        .try_into()
+        .map_err(|v: Vec<u8>| anyhow::anyhow!("MK fingerprint has {} bytes, expected {}", v.len(), FINGERPRINT_LEN))
+}
@@ -3,7 +3,11 @@ use std::process::Command;
 use std::path::{Path, PathBuf};
 use std::fs;
 use log::info;
-use dialoguer::{Select, theme::ColorfulTheme};
+use dialoguer::{Password, Select, theme::ColorfulTheme};
+use rand::RngCore;
+use tuff_crypto::key_file::{self, KdfParams, KeyFileError};
+use zeroize::Zeroizing;
+use tuff_common::paths::SYSTEM_UUID_PATH;
 
 pub struct UsbKeyStore;
 
@@ -31,9 +35,8 @@ impl UsbKeyStore {
     /// Reads the system DMI Product UUID
     pub fn get_system_uuid() -> Result<String> {
         // Try reading from sysfs (requires root)
-        let uuid_path = "/sys/class/dmi/id/product_uuid";
-        if Path::new(uuid_path).exists() {
-            let uuid = fs::read_to_string(uuid_path)?.trim().to_string();
+        if Path::new(SYSTEM_UUID_PATH).exists() {
+            let uuid = fs::read_to_string(SYSTEM_UUID_PATH)?.trim().to_string();
             if !uuid.is_empty() {
                 return Ok(uuid);
             }
@@ -41,11 +44,11 @@ impl UsbKeyStore {
 
         // Fallback or Error? For TUFF-OS security, we prefer unique binding.
         // But for dev env (QEMU), we might accept a fallback if explicit.
-        bail!("Could not read system UUID from /sys/class/dmi/id/product_uuid. Are you root?");
+        bail!("Could not read system UUID from {}. Are you root?", SYSTEM_UUID_PATH);
     }
 
     /// Interactive selection of USB device
-    pub fn select_usb_device() -> Result<PathBuf> {
+    pub fn select_usb_device(prompt: &str) -> Result<PathBuf> {
         let devices = Self::find_usb_devices()?;
         if devices.is_empty() {
             bail!("No USB devices (part1) found. Please insert a formatted USB drive.");
@@ -56,7 +59,7 @@ impl UsbKeyStore {
             .collect();
 
         let selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select USB Device to store the Master Key")
+            .with_prompt(prompt)
             .default(0)
             .items(&selections)
             .interact()?;
@@ -64,24 +67,23 @@ impl UsbKeyStore {
         Ok(devices[selection].clone())
     }
 
+    /// Writes `key` wrapped under a passphrase chosen at the prompt.
     pub fn write_key_to_usb(device_path: &Path, key: &[u8; 32], uuid: &str) -> Result<()> {
-        // 1. Mount
-        let mount_point = Path::new("/mnt/usb_tmp");
-        if !mount_point.exists() {
-            fs::create_dir_all(mount_point)?;
-        }
-
-        // Unmount just in case it was already mounted
-        let _ = Command::new("umount").arg(mount_point).status();
-
-        let status = Command::new("mount")
-            .arg(device_path)
-            .arg(mount_point)
-            .status().context("Failed to run mount command")?;
+        let passphrase = Zeroizing::new(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt("Passphrase protecting the key file")
+                .with_confirmation("Repeat passphrase", "Passphrases do not match")
+                .interact()?,
+        );
+        let mut salt = [0u8; key_file::SALT_LEN];
+        let mut nonce = [0u8; key_file::NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let contents = key_file::wrap(key, passphrase.as_bytes(), uuid, KdfParams::default(), &salt, &nonce)
+            .map_err(|e| anyhow::anyhow!("Cannot wrap key: {:?}", e))?;
 
-        if !status.success() {
-            bail!("Could not mount USB device {:?}", device_path);
-        }
+        // 1. Mount
+        let mount_point = Self::mount(device_path)?;
 
         // 2. Write Key
         let key_dir = mount_point.join("TUFF_KEYS");
@@ -90,11 +92,11 @@ impl UsbKeyStore {
         }
 
         let key_file = key_dir.join(format!("{}.key", uuid));
-        fs::write(&key_file, key).context("Failed to write key file to USB")?;
+        fs::write(&key_file, &contents).context("Failed to write key file to USB")?;
 
         // 3. Verify Write
         let read_back = fs::read(&key_file).context("Failed to read back key for verification")?;
-        if read_back != key {
+        if read_back != contents {
             // Try to cleanup before erroring
             let _ = Command::new("umount").arg(mount_point).status();
             bail!("Verification failed: Written key does not match memory key");
@@ -113,4 +115,77 @@ impl UsbKeyStore {
 
         Ok(())
     }
+
+    /// Reads the master key bound to `uuid` back from the USB key store,
+    /// prompting for the passphrase. Legacy raw key files are refused unless
+    /// `allow_legacy` is set.
+    pub fn read_key_from_usb(device_path: &Path, uuid: &str, allow_legacy: bool) -> Result<[u8; 32]> {
+        let mount_point = Self::mount(device_path)?;
+
+        let key_file = mount_point.join("TUFF_KEYS").join(format!("{}.key", uuid));
+        let result = fs::read(&key_file)
+            .with_context(|| format!("No key for UUID {} on {:?}", uuid, device_path));
+
+        let _ = Command::new("umount").arg(mount_point).status();
+
+        let data = Zeroizing::new(result?);
+        if key_file::is_legacy(&data) {
+            if !allow_legacy {
+                bail!("{:?} is a legacy raw key; pass --allow-legacy-key to use it", key_file);
+            }
+            let mut key = [0u8; 32];
+            key.copy_from_slice(&data);
+            return Ok(key);
+        }
+        if !key_file::is_wrapped(&data) {
+            bail!("Invalid key file {:?}: {} bytes", key_file, data.len());
+        }
+
+        for _ in 0..3 {
+            let passphrase = Zeroizing::new(
+                Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Key file passphrase")
+                    .interact()?,
+            );
+            match key_file::unwrap(&data, passphrase.as_bytes(), uuid) {
+                Ok(key) => return Ok(*key),
+                Err(KeyFileError::Authentication) => eprintln!("Wrong passphrase."),
+                Err(e) => bail!("Cannot unwrap {:?}: {:?}", key_file, e),
+            }
+        }
+        bail!("Too many wrong passphrases");
+    }
+
+    /// Reads `name` from the root of the USB stick, if present.
+    pub fn read_root_file(device_path: &Path, name: &str) -> Result<Option<Vec<u8>>> {
+        let mount_point = Self::mount(device_path)?;
+        let path = mount_point.join(name);
+        let result = if path.exists() {
+            fs::read(&path).with_context(|| format!("Cannot read {} on {:?}", name, device_path)).map(Some)
+        } else {
+            Ok(None)
+        };
+        let _ = Command::new("umount").arg(mount_point).status();
+        result
+    }
+
+    fn mount(device_path: &Path) -> Result<&'static Path> {
+        let mount_point = Path::new("/mnt/usb_tmp");
+        if !mount_point.exists() {
+            fs::create_dir_all(mount_point)?;
+        }
+
+        // Unmount just in case it was already mounted
+        let _ = Command::new("umount").arg(mount_point).status();
+
+        let status = Command::new("mount")
+            .arg(device_path)
+            .arg(mount_point)
+            .status().context("Failed to run mount command")?;
+
+        if !status.success() {
+            bail!("Could not mount USB device {:?}", device_path);
+        }
+        Ok(mount_point)
+    }
 }
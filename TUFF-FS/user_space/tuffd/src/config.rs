@@ -0,0 +1,47 @@
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use tuff_common::paths::TUFFD_CONFIG_PATH;
+
+/// What tuffd does when the Master Key USB is pulled while a volume is open.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyRemovalPolicy {
+    /// Stop accepting writes, drain the write queue to disk, then lock.
+    PendingOnly,
+    /// Stop all I/O at once (queued writes stay on disk for the next unlock), then lock.
+    Freeze,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TuffdConfig {
+    pub key_removal: KeyRemovalPolicy,
+    /// Accept bare 32-byte key files written before passphrase wrapping.
+    pub allow_legacy_key: bool,
+}
+
+impl Default for TuffdConfig {
+    fn default() -> Self {
+        Self { key_removal: KeyRemovalPolicy::PendingOnly, allow_legacy_key: false }
+    }
+}
+
+impl TuffdConfig {
+    /// Reads `TUFFD_CONFIG_PATH`; a missing or unreadable file yields the defaults.
+    pub fn load() -> Self {
+        match fs::read_to_string(TUFFD_CONFIG_PATH) {
+            Ok(text) => match serde_json::from_str(&text) {
+                Ok(config) => config,
+                Err(e) => {
+                    error!("Ignoring malformed {}: {}", TUFFD_CONFIG_PATH, e);
+                    Self::default()
+                }
+            },
+            Err(_) => {
+                info!("No {}; using default configuration.", TUFFD_CONFIG_PATH);
+                Self::default()
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use anyhow::{Context, Result};
+use nix::sys::termios::{tcgetattr, tcsetattr, LocalFlags, SetArg};
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader, Write};
+use zeroize::Zeroizing;
+
+/// tuffd runs as PID 1 without a controlling terminal, so prompts go to the console.
+const CONSOLE: &str = "/dev/console";
+
+/// Prompts on the system console and reads one line with echo turned off.
+pub fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>> {
+    let mut tty = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(CONSOLE)
+        .with_context(|| format!("Cannot open {}", CONSOLE))?;
+
+    let saved = tcgetattr(&tty).context("Console is not a terminal")?;
+    let mut silent = saved.clone();
+    silent.local_flags.remove(LocalFlags::ECHO);
+    silent.local_flags.insert(LocalFlags::ECHONL);
+    tcsetattr(&tty, SetArg::TCSAFLUSH, &silent)?;
+
+    let mut line = Zeroizing::new(String::new());
+    let read = tty
+        .write_all(prompt.as_bytes())
+        .and_then(|_| tty.flush())
+        .and_then(|_| BufReader::new(&tty).read_line(&mut line));
+    // Restore echo even when the read failed.
+    tcsetattr(&tty, SetArg::TCSAFLUSH, &saved)?;
+    read.context("Cannot read passphrase")?;
+
+    let trimmed = line.trim_end_matches(['\r', '\n']).len();
+    line.truncate(trimmed);
+    Ok(line)
+}
@@ -0,0 +1,417 @@
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{mpsc, oneshot};
+use tuff_common::control::{self, IndexInfo, MemberInfo, Request, Response, VolumeInfo};
+use tuff_common::truncate::Retention;
+use tuff_crypto::key_manager::KeyManager;
+use zeroize::Zeroizing;
+
+use crate::events::{TuffLogEntry, LogLevel, TuffEvent};
+use crate::disk_probe::{self, MemberDisk};
+use crate::fs_manager::{self, FsManager};
+use crate::mk_fingerprint::verify_mk_fingerprint;
+use crate::state_machine::{State, SystemState};
+use crate::usb_monitor::{self, KeyWatch};
+
+/// A request received on the control socket, answered by the main loop.
+pub struct ControlCall {
+    pub request: Request,
+    pub reply: oneshot::Sender<Response>,
+}
+
+/// Binds the control socket (root only) and forwards every request to the
+/// returned channel.
+pub fn spawn(socket: &Path) -> Result<mpsc::Receiver<ControlCall>> {
+    // The socket is reachable only through a root-only directory, so there is
+    // no window between bind and chmod in which others can connect.
+    if let Some(parent) = socket.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(parent)
+            .with_context(|| format!("Failed to create {}", parent.display()))?;
+        fs::set_permissions(parent, fs::Permissions::from_mode(0o700))?;
+    }
+    // A socket left behind by a previous instance would make bind fail.
+    let _ = fs::remove_file(socket);
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("Failed to bind {}", socket.display()))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))?;
+
+    let (tx, rx) = mpsc::channel(16);
+    tokio::spawn(async move {
+        loop {
+            match listener.accept().await {
+                Ok((stream, _)) => {
+                    let tx = tx.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = serve(stream, tx).await {
+                            debug!("Control connection closed: {}", e);
+                        }
+                    });
+                }
+                Err(e) => warn!("Control socket accept failed: {}", e),
+            }
+        }
+    });
+    Ok(rx)
+}
+
+async fn serve(mut stream: UnixStream, tx: mpsc::Sender<ControlCall>) -> Result<()> {
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    // Unfreeze requests carry the Master Key.
+    let mut body = Zeroizing::new(vec![0u8; control::frame_len(header)?]);
+    stream.read_exact(&mut body).await?;
+
+    let request = control::decode_body::<Request>(&body);
+    drop(body);
+    let response = match request {
+        Ok(request) => {
+            let (reply, answer) = oneshot::channel();
+            tx.send(ControlCall { request, reply }).await
+                .context("tuffd main loop has stopped")?;
+            answer.await.context("tuffd dropped the request")?
+        }
+        Err(e) => Response::Error { message: e.to_string() },
+    };
+    stream.write_all(&control::encode_frame(&response)?).await?;
+    Ok(())
+}
+
+/// What a control request may see and change of the running daemon.
+pub struct DaemonContext<'a> {
+    pub state: &'a mut SystemState,
+    pub keys: &'a mut KeyManager,
+    pub volume: &'a mut Option<FsManager>,
+    pub volumes: &'a mut BTreeMap<String, Vec<MemberDisk>>,
+    pub key_watch: &'a mut Option<KeyWatch>,
+    pub allow_legacy_key: bool,
+    /// Where the MK fingerprint of this machine is recorded.
+    pub mk_fingerprint_path: &'a Path,
+}
+
+pub fn handle(request: Request, mut ctx: DaemonContext<'_>) -> Response {
+    let state = ctx.state.current();
+    let result = match request {
+        Request::Status => Ok(status(&ctx)),
+        Request::Unfreeze { master_key, reason } => unfreeze(&mut ctx, master_key, &reason),
+        Request::Put { name, data } => put(state, ctx.volume.as_mut(), &name, &data),
+        Request::Commit => commit(state, ctx.volume.as_mut()),
+        Request::Truncate { keep, max_age_secs, dry_run } => {
+            let retention = Retention { keep, max_age_secs };
+            truncate(state, ctx.volume.as_mut(), retention, dry_run)
+        }
+    };
+    result.unwrap_or_else(|e| Response::Error { message: format!("{:#}", e) })
+}
+
+fn status(ctx: &DaemonContext<'_>) -> Response {
+    let index = ctx.volume.as_ref().and_then(|fs| {
+        fs.current().map(|current| IndexInfo {
+            volume_uuid: fs.volume_uuid().to_string(),
+            generation: current.generation,
+            timestamp: current.timestamp,
+            replicas: current.replicas.clone(),
+        })
+    });
+    let open_uuid = ctx.volume.as_ref().map(|fs| fs.volume_uuid());
+    let volumes = ctx
+        .volumes
+        .iter()
+        .map(|(uuid, members)| VolumeInfo {
+            volume_uuid: uuid.clone(),
+            members: members
+                .iter()
+                .map(|m| MemberInfo { device: m.device.display().to_string(), hw_id: m.hw_id })
+                .collect(),
+            open: open_uuid == Some(uuid.as_str()),
+        })
+        .collect();
+    Response::Status {
+        version: env!("CARGO_PKG_VERSION").to_string(),
+        state: ctx.state.current(),
+        key_loaded: ctx.keys.has_key(),
+        index,
+        volumes,
+    }
+}
+
+/// Re-runs the key and volume checks of WaitKey with an admin-supplied key and
+/// only then leaves Freeze. Every attempt is audited.
+fn unfreeze(ctx: &mut DaemonContext<'_>, master_key: Zeroizing<Vec<u8>>, reason: &str) -> Result<Response> {
+    if ctx.state.current() != State::Freeze {
+        anyhow::bail!("tuffd is in {:?}, not Freeze", ctx.state.current());
+    }
+    if reason.trim().is_empty() {
+        anyhow::bail!("An unfreeze needs a reason for the audit log");
+    }
+
+    let attempt = (|| -> Result<(KeyManager, FsManager)> {
+        let mut keys = KeyManager::new();
+        keys.load_key(&master_key)
+            .map_err(|e| anyhow::anyhow!("Unusable key material: {:?}", e))?;
+        let fingerprint = keys.fingerprint().expect("key loaded above");
+        verify_mk_fingerprint(ctx.mk_fingerprint_path, &fingerprint)?;
+        *ctx.volumes = disk_probe::probe_volumes_or_empty();
+        let fs = fs_manager::open_matching(&keys, &fingerprint, ctx.volumes)?;
+        Ok((keys, fs))
+    })();
+
+    let (keys, fs) = match attempt {
+        Ok(verified) => verified,
+        Err(e) => {
+            TuffLogEntry::new(
+                LogLevel::Audit,
+                TuffEvent::UnfreezeRejected { reason: reason.to_string(), error: format!("{:#}", e) },
+            ).log();
+            return Err(e);
+        }
+    };
+
+    let volume_uuid = fs.volume_uuid().to_string();
+    let generation = fs.current().expect("opened with an index").generation;
+    TuffLogEntry::new(
+        LogLevel::Audit,
+        TuffEvent::AdminUnfreeze {
+            reason: reason.to_string(),
+            volume_uuid: volume_uuid.clone(),
+            generation,
+        },
+    ).log();
+
+    *ctx.keys = keys;
+    *ctx.volume = Some(fs);
+    // The key removal policy applies again from here on.
+    *ctx.key_watch = match usb_monitor::find_key_device(ctx.allow_legacy_key) {
+        Ok(Some(device)) => Some(KeyWatch::new(device)),
+        Ok(None) => {
+            warn!("No key stick present after unfreeze; key removal is not watched");
+            None
+        }
+        Err(e) => {
+            warn!("Key stick scan failed after unfreeze; key removal is not watched: {:#}", e);
+            None
+        }
+    };
+    ctx.state.transition_to(State::Normal);
+    TuffLogEntry::new(
+        LogLevel::Warn,
+        TuffEvent::StateTransition {
+            from: State::Freeze,
+            to: State::Normal,
+            reason: format!("Admin unfreeze: {}", reason),
+        },
+    ).log();
+    Ok(Response::Unfrozen { volume_uuid, generation })
+}
+
+/// Index writes are only allowed in Normal.
+fn writable(state: State, volume: Option<&mut FsManager>) -> Result<&mut FsManager> {
+    match state {
+        State::Normal => {}
+        State::Freeze => anyhow::bail!("tuffd is in Freeze; writes are refused until an admin intervenes"),
+        State::PendingOnly => anyhow::bail!("tuffd is in PendingOnly; no new generation can be written"),
+        other => anyhow::bail!("tuffd is in {:?}; no volume is open for writing", other),
+    }
+    volume.context("No volume is open")
+}
+
+fn put(state: State, volume: Option<&mut FsManager>, name: &str, data: &[u8]) -> Result<Response> {
+    let fs = writable(state, volume)?;
+    let size = fs.stage_file(state, name, data)?.size;
+    debug!("Staged {} ({} bytes) on volume {}", name, size, fs.volume_uuid());
+    Ok(Response::Staged { name: name.to_string(), size, queue_depth: fs.queue_depth() })
+}
+
+fn commit(state: State, volume: Option<&mut FsManager>) -> Result<Response> {
+    let fs = writable(state, volume)?;
+
+    // The commit drains the write queue first and carries the current entries
+    // into the next generation.
+    let entries = fs.current_entries()?;
+    let committed = fs.commit(&entries)?;
+    let (generation, timestamp) = (committed.generation, committed.timestamp);
+    TuffLogEntry::new(
+        LogLevel::Audit,
+        TuffEvent::IndexCommitted {
+            volume_uuid: fs.volume_uuid().to_string(),
+            generation,
+            entries: entries.len(),
+        },
+    ).log();
+    Ok(Response::Committed { generation, timestamp, entries: entries.len() })
+}
+
+fn truncate(
+    state: State,
+    volume: Option<&mut FsManager>,
+    retention: Retention,
+    dry_run: bool,
+) -> Result<Response> {
+    if retention.keep.is_none() && retention.max_age_secs.is_none() {
+        anyhow::bail!("Truncate needs a retention count or age");
+    }
+    let fs = writable(state, volume)?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+    let plan = fs.truncate(&retention, now, dry_run)?;
+    if !dry_run {
+        TuffLogEntry::new(
+            LogLevel::Audit,
+            TuffEvent::IndexTruncated {
+                volume_uuid: fs.volume_uuid().to_string(),
+                dropped: plan.dropped.clone(),
+                freed_chunks: plan.freed.len(),
+            },
+        ).log();
+    }
+    Ok(Response::Truncated {
+        retained: plan.retained,
+        dropped: plan.dropped,
+        freed: plan.freed,
+        dry_run,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn in_state(path: &[State]) -> SystemState {
+        let mut state = SystemState::new();
+        for &next in path {
+            assert!(state.transition_to(next));
+        }
+        state
+    }
+
+    fn call(request: Request, state: &mut SystemState, volumes: &mut BTreeMap<String, Vec<MemberDisk>>) -> Response {
+        call_with_record(request, state, volumes, Path::new("/nonexistent/mk_fingerprint"))
+    }
+
+    fn call_with_record(
+        request: Request,
+        state: &mut SystemState,
+        volumes: &mut BTreeMap<String, Vec<MemberDisk>>,
+        mk_fingerprint_path: &Path,
+    ) -> Response {
+        let ctx = DaemonContext {
+            state,
+            keys: &mut KeyManager::new(),
+            volume: &mut None,
+            volumes,
+            key_watch: &mut None,
+            allow_legacy_key: false,
+            mk_fingerprint_path,
+        };
+        handle(request, ctx)
+    }
+
+    fn error_of(response: Response) -> String {
+        match response {
+            Response::Error { message } => message,
+            other => panic!("expected an error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn status_reports_state_and_probed_volumes() {
+        let mut volumes = BTreeMap::new();
+        volumes.insert(
+            "vol".to_string(),
+            vec![MemberDisk {
+                device: PathBuf::from("/dev/sdb"),
+                volume_uuid: "vol".into(),
+                hw_id: 7,
+                sector_size: 4096,
+                mk_fingerprint: vec![],
+            }],
+        );
+        let mut state = in_state(&[State::WaitKey]);
+        match call(Request::Status, &mut state, &mut volumes) {
+            Response::Status { state, key_loaded, index, volumes, .. } => {
+                assert_eq!(state, State::WaitKey);
+                assert!(!key_loaded);
+                assert!(index.is_none());
+                assert_eq!(
+                    volumes,
+                    vec![VolumeInfo {
+                        volume_uuid: "vol".into(),
+                        members: vec![MemberInfo { device: "/dev/sdb".into(), hw_id: 7 }],
+                        open: false,
+                    }]
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn writes_are_refused_outside_normal() {
+        let mut volumes = BTreeMap::new();
+        let mut frozen = in_state(&[State::Freeze]);
+        assert!(error_of(call(Request::Commit, &mut frozen, &mut volumes)).contains("Freeze"));
+
+        let mut pending = in_state(&[State::WaitKey, State::Normal, State::PendingOnly]);
+        assert!(error_of(call(Request::Commit, &mut pending, &mut volumes)).contains("PendingOnly"));
+        let put = Request::Put { name: "a".into(), data: vec![1; 8] };
+        assert!(error_of(call(put, &mut pending, &mut volumes)).contains("PendingOnly"));
+
+        let mut normal = in_state(&[State::WaitKey, State::Normal]);
+        assert!(error_of(call(Request::Commit, &mut normal, &mut volumes)).contains("No volume"));
+
+        let no_retention = Request::Truncate { keep: None, max_age_secs: None, dry_run: true };
+        assert!(error_of(call(no_retention, &mut normal, &mut volumes)).contains("retention"));
+        let truncate = Request::Truncate { keep: Some(2), max_age_secs: None, dry_run: true };
+        assert!(error_of(call(truncate, &mut frozen, &mut volumes)).contains("Freeze"));
+    }
+
+    #[test]
+    fn unfreeze_checks_state_reason_and_key_before_leaving_freeze() {
+        let mut volumes = BTreeMap::new();
+        let unfreeze = |key: Vec<u8>, reason: &str| Request::Unfreeze {
+            master_key: Zeroizing::new(key),
+            reason: reason.into(),
+        };
+
+        let mut waiting = in_state(&[State::WaitKey]);
+        assert!(error_of(call(unfreeze(vec![1; 32], "ops"), &mut waiting, &mut volumes)).contains("not Freeze"));
+        assert_eq!(waiting.current(), State::WaitKey);
+
+        let mut frozen = in_state(&[State::Freeze]);
+        assert!(error_of(call(unfreeze(vec![1; 32], "  "), &mut frozen, &mut volumes)).contains("reason"));
+        assert!(error_of(call(unfreeze(vec![1; 16], "ops"), &mut frozen, &mut volumes)).contains("Unusable key"));
+        assert_eq!(frozen.current(), State::Freeze);
+    }
+
+    #[test]
+    fn rejected_unfreeze_leaves_the_fingerprint_record_alone() {
+        let dir = std::env::temp_dir().join(format!("tuff_unfreeze_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let record = dir.join("mk_fingerprint");
+        let mut volumes = BTreeMap::new();
+        let mut frozen = in_state(&[State::Freeze]);
+        let unfreeze = || Request::Unfreeze { master_key: Zeroizing::new(vec![1; 32]), reason: "ops".into() };
+
+        // No record yet: an unfreeze must not be the one to create it.
+        let message = error_of(call_with_record(unfreeze(), &mut frozen, &mut volumes, &record));
+        assert!(message.contains("No MK fingerprint"), "{}", message);
+        assert!(!record.exists());
+
+        let other = "00".repeat(32);
+        fs::write(&record, &other).unwrap();
+        let message = error_of(call_with_record(unfreeze(), &mut frozen, &mut volumes, &record));
+        assert!(message.contains("does not match"), "{}", message);
+        assert_eq!(fs::read_to_string(&record).unwrap(), other);
+        assert_eq!(frozen.current(), State::Freeze);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+use anyhow::Result;
+use log::{debug, error};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tuff_common::block_device::{BlockDevice, RawBlockDevice, CHUNK_SIZE};
+use tuff_common::schemas::{parse_initial_chunk, validate_initial_chunk};
+use crate::events::{TuffLogEntry, LogLevel, TuffEvent};
+
+/// A physical disk whose LBA 0 carries a valid `InitialChunk`.
+#[derive(Debug, Clone)]
+pub struct MemberDisk {
+    pub device: PathBuf,
+    pub volume_uuid: String,
+    pub hw_id: u64,
+    pub sector_size: u32,
+    pub mk_fingerprint: Vec<u8>,
+}
+
+/// Reads LBA 0 of every attached disk and groups TUFF-FS members by volume UUID.
+pub fn probe_volumes() -> Result<BTreeMap<String, Vec<MemberDisk>>> {
+    let mut volumes: BTreeMap<String, Vec<MemberDisk>> = BTreeMap::new();
+
+    for device in scan_block_devices()? {
+        match probe_device(&device) {
+            Ok(Some(member)) => {
+                TuffLogEntry::new(
+                    LogLevel::Info,
+                    TuffEvent::VolumeMemberFound {
+                        device: member.device.to_string_lossy().to_string(),
+                        volume_uuid: member.volume_uuid.clone(),
+                        hw_id: member.hw_id,
+                    },
+                ).log();
+                let members = volumes.entry(member.volume_uuid.clone()).or_default();
+                if members.iter().any(|m| m.hw_id == member.hw_id) {
+                    TuffLogEntry::new(
+                        LogLevel::Warn,
+                        TuffEvent::IoError {
+                            context: format!("Duplicate hw_id {:#x} in volume {}", member.hw_id, member.volume_uuid),
+                            error: format!("{} ignored", member.device.display()),
+                        },
+                    ).log();
+                    continue;
+                }
+                members.push(member);
+            }
+            Ok(None) => debug!("{:?} is not a TUFF-FS member", device),
+            Err(e) => {
+                TuffLogEntry::new(
+                    LogLevel::Warn,
+                    TuffEvent::IoError {
+                        context: format!("Probe of {} failed", device.display()),
+                        error: e.to_string(),
+                    },
+                ).log();
+            }
+        }
+    }
+    Ok(volumes)
+}
+
+/// Like `probe_volumes`, but a failed scan counts as no volumes attached.
+pub fn probe_volumes_or_empty() -> BTreeMap<String, Vec<MemberDisk>> {
+    probe_volumes().unwrap_or_else(|e| {
+        error!("Disk probe failed: {}", e);
+        BTreeMap::new()
+    })
+}
+
+/// Returns `Ok(None)` when the device is readable but carries no TUFF-FS anchor.
+pub fn probe_device(device: &Path) -> Result<Option<MemberDisk>> {
+    let dev = RawBlockDevice::open_readonly(device)?;
+    if dev.chunk_count() == 0 {
+        return Ok(None);
+    }
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    dev.read_chunk(0, &mut buf)?;
+
+    if validate_initial_chunk(&buf).is_err() {
+        return Ok(None);
+    }
+    let chunk = parse_initial_chunk(&buf)?;
+    Ok(Some(MemberDisk {
+        device: device.to_path_buf(),
+        volume_uuid: chunk.volume_uuid().unwrap_or_default().to_string(),
+        hw_id: chunk.hw_id(),
+        sector_size: chunk.sector_size(),
+        mk_fingerprint: chunk.mk_fingerprint().map(|f| f.to_vec()).unwrap_or_default(),
+    }))
+}
+
+fn scan_block_devices() -> Result<Vec<PathBuf>> {
+    let mut devices = Vec::new();
+    let sys_block = Path::new("/sys/block");
+
+    if !sys_block.exists() {
+        return Ok(devices);
+    }
+
+    for entry in fs::read_dir(sys_block)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+
+        // Skip RAM disks and optical/floppy drives; they never carry TUFF-FS.
+        if ["ram", "zram", "sr", "fd"].iter().any(|p| name.starts_with(p)) {
+            continue;
+        }
+        // Loop devices count only while attached, so image files can be probed.
+        if name.starts_with("loop") && !entry.path().join("loop/backing_file").exists() {
+            continue;
+        }
+
+        let dev_path = Path::new("/dev").join(&name);
+        if dev_path.exists() {
+            devices.push(dev_path);
+        }
+    }
+    devices.sort();
+    Ok(devices)
+}
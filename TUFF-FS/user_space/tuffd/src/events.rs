@@ -1,5 +1,6 @@
 use serde::{Serialize, Deserialize};
 use std::time::{SystemTime, UNIX_EPOCH};
+use crate::config::KeyRemovalPolicy;
 use crate::state_machine::State;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -26,8 +27,17 @@ pub enum TuffEvent {
     KeyDetected { device: String, key_uuid: String },
     KeyRejected { device: String, reason: String },
     KeyMismatch { reason: String },
+    KeyRemoved { device: String, policy: KeyRemovalPolicy },
     MountSuccess { path: String },
     MountFailure { path: String, error: String },
+    VolumeMemberFound { device: String, volume_uuid: String, hw_id: u64 },
+    AdminUnfreeze { reason: String, volume_uuid: String, generation: u8 },
+    UnfreezeRejected { reason: String, error: String },
+    WriteQueueDepth { volume_uuid: String, depth: usize },
+    IndexCommitted { volume_uuid: String, generation: u8, entries: usize },
+    IndexTruncated { volume_uuid: String, dropped: Vec<u8>, freed_chunks: usize },
+    ChildSpawned { name: String, pid: i32 },
+    ChildExited { name: String, pid: i32, status: String, restart_in_secs: Option<u64> },
     IoError { context: String, error: String },
 }
 
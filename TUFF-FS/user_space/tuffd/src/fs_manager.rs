@@ -1,32 +1,283 @@
-use anyhow::Result;
-use std::fs;
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::{BTreeMap, BTreeSet};
 use std::path::Path;
-use tuff_common::paths::{INDEX_CHUNK_CURRENT, INDEX_CHUNK_PREV, INDEX_DIR};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tuff_common::block_device::{BlockDevice, RawBlockDevice};
+use tuff_common::data_chunk::{chain_addrs, chunks_needed, seal_chain, ChunkAddr, MemberMap};
+use tuff_common::index_store::{self, LoadedIndex};
+use tuff_common::layout::DATA_START;
+use tuff_common::paths::WRITE_QUEUE_DIR;
+use tuff_common::truncate::{self, Retention, TruncatePlan};
+use tuff_crypto::chunk_cipher::ChunkCipher;
+use tuff_crypto::key_manager::{KeyManager, SubkeyPurpose};
+use tuff_common::schemas::{parse_index_chunk, verify_index_chain};
+use tuff_common::tuff_schemas::tuff::tuff_os::FileEntryT;
 
-// Placeholder for future FS operations implementation.
-#[allow(dead_code)]
-pub struct FsManager;
+use crate::disk_probe::MemberDisk;
+use crate::state_machine::State;
+use crate::write_queue::WriteQueue;
+
+/// An opened volume: every member disk plus the current IndexChunk generation.
+pub struct FsManager {
+    volume_uuid: String,
+    members: MemberMap,
+    cipher: ChunkCipher,
+    queue: WriteQueue,
+    current: Option<LoadedIndex>,
+    /// Files written since the last commit, by name; the next commit publishes them.
+    staged: BTreeMap<String, FileEntryT>,
+    /// Chunks handed out to staged files. No committed generation references
+    /// them yet, so the allocator has to be told.
+    reserved: BTreeSet<ChunkAddr>,
+}
 
 impl FsManager {
-    pub fn load_latest_index_chunk(&self) -> Result<Option<Vec<u8>>> {
-        if !Path::new(INDEX_CHUNK_CURRENT).exists() {
-            return Ok(None);
+    /// `data_key` is the volume's data-encryption subkey.
+    pub fn open(volume_uuid: &str, disks: &[MemberDisk], data_key: &[u8; 32]) -> Result<Self> {
+        let mut members: MemberMap = BTreeMap::new();
+        for disk in disks {
+            let dev = RawBlockDevice::open(&disk.device)
+                .with_context(|| format!("Cannot open member {:?}", disk.device))?;
+            members.insert(disk.hw_id, Box::new(dev) as Box<dyn BlockDevice>);
         }
-        let data = fs::read(INDEX_CHUNK_CURRENT)?;
-        Ok(Some(data))
+        let queue = WriteQueue::open(&Path::new(WRITE_QUEUE_DIR).join(volume_uuid))?;
+        Ok(Self {
+            volume_uuid: volume_uuid.to_string(),
+            members,
+            cipher: ChunkCipher::new(data_key),
+            queue,
+            current: None,
+            staged: BTreeMap::new(),
+            reserved: BTreeSet::new(),
+        })
     }
 
-    pub fn write_latest_index_chunk(&self, data: &[u8]) -> Result<()> {
-        fs::create_dir_all(INDEX_DIR)?;
+    pub fn volume_uuid(&self) -> &str {
+        &self.volume_uuid
+    }
 
-        let tmp_path = format!("{}.tmp", INDEX_CHUNK_CURRENT);
-        fs::write(&tmp_path, data)?;
+    pub fn current(&self) -> Option<&LoadedIndex> {
+        self.current.as_ref()
+    }
 
-        if Path::new(INDEX_CHUNK_CURRENT).exists() {
-            let _ = fs::rename(INDEX_CHUNK_CURRENT, INDEX_CHUNK_PREV);
-        }
+    /// Loads the newest committed generation found on any replica.
+    pub fn load_latest_index_chunk(&mut self) -> Result<Option<&LoadedIndex>> {
+        self.current = index_store::load_latest(&self.members)?;
+        Ok(self.current.as_ref())
+    }
+
+    /// Walks the retained history behind the current generation and checks
+    /// every `prev_chunk_hash` link.
+    pub fn verify_history(&self) -> Result<()> {
+        let current = self.current.as_ref().context("No IndexChunk loaded")?;
+        let history = index_store::load_history(&self.members, current)?;
+        let chain: Vec<&[u8]> = history.iter().map(|d| d.as_slice()).collect();
+        verify_index_chain(&chain)
+            .map_err(|e| anyhow::anyhow!("IndexChunk hash chain broken: {}", e))
+    }
+
+    pub fn queue_depth(&self) -> usize {
+        self.queue.depth()
+    }
+
+    /// Writes up to `max` queued chunks to the members.
+    pub fn drain_queue(&mut self, max: usize) -> Result<usize> {
+        self.queue.drain(&self.members, max)
+    }
 
-        fs::rename(&tmp_path, INDEX_CHUNK_CURRENT)?;
+    /// Seals `data` as a chain over `addrs` and queues every chunk.
+    /// The queue only grows in Normal; PendingOnly is there to let it drain.
+    pub fn stage_chain(&mut self, state: State, addrs: &[ChunkAddr], data: &[u8]) -> Result<()> {
+        if state != State::Normal {
+            anyhow::bail!("tuffd is in {:?}; no new writes are queued", state);
+        }
+        for (addr, sealed) in seal_chain(&self.members, &self.cipher, addrs, data)? {
+            self.queue.enqueue(addr, &sealed)?;
+        }
         Ok(())
     }
+
+    /// Writes `data` to free chunks through the queue and stages an entry for
+    /// it under `name`, replacing any earlier one. Staged entries live in
+    /// memory only: if tuffd stops before the next commit, their chunks are
+    /// still written but nothing references them.
+    pub fn stage_file(&mut self, state: State, name: &str, data: &[u8]) -> Result<&FileEntryT> {
+        if name.is_empty() {
+            anyhow::bail!("File name is empty");
+        }
+        let addrs = self.allocate(chunks_needed(data.len() as u64, &self.cipher))?;
+        self.stage_chain(state, &addrs, data)?;
+        self.reserved.extend(addrs.iter().copied());
+
+        let start = addrs.first().copied().unwrap_or(ChunkAddr::END);
+        let mut entry = FileEntryT::default();
+        entry.name = name.to_string();
+        entry.size = data.len() as u64;
+        entry.mtime = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        entry.mode = 0o644;
+        entry.start_hw_id = start.hw_id;
+        entry.start_chunk_id = start.chunk_id;
+        self.staged.insert(name.to_string(), entry);
+        Ok(&self.staged[name])
+    }
+
+    /// Picks the lowest `count` data chunks that no committed generation on the
+    /// ring reaches and no staged file holds, members in `hw_id` order.
+    fn allocate(&self, count: u64) -> Result<Vec<ChunkAddr>> {
+        if count == 0 {
+            return Ok(Vec::new());
+        }
+        let mut used = self.reserved.clone();
+        for index in index_store::load_committed(&self.members)? {
+            for entry in parse_index_chunk(&index.data)?.entries().iter().flatten() {
+                let start = ChunkAddr { hw_id: entry.start_hw_id(), chunk_id: entry.start_chunk_id() };
+                if start.is_end() {
+                    continue;
+                }
+                // Same rule as truncate: a chain that cannot be followed
+                // might still own chunks, so nothing is handed out.
+                let addrs = chain_addrs(&self.members, &self.cipher, start).with_context(|| {
+                    format!("generation {}: cannot follow {}", index.generation, entry.name())
+                })?;
+                used.extend(addrs);
+            }
+        }
+
+        let mut free = Vec::new();
+        for (&hw_id, dev) in &self.members {
+            let candidates = (DATA_START..dev.chunk_count())
+                .map(|chunk_id| ChunkAddr { hw_id, chunk_id })
+                .filter(|addr| !used.contains(addr));
+            free.extend(candidates.take((count - free.len() as u64) as usize));
+            if free.len() as u64 == count {
+                return Ok(free);
+            }
+        }
+        anyhow::bail!("Volume is full: {} free chunks, {} needed", free.len(), count)
+    }
+
+    /// Drains the write queue, then commits the next generation to every
+    /// replica, chained to the current one. Staged files are published by it.
+    pub fn commit(&mut self, entries: &[FileEntryT]) -> Result<&LoadedIndex> {
+        self.queue.drain(&self.members, usize::MAX)?;
+        let prev = self
+            .current
+            .as_ref()
+            .context("No IndexChunk loaded; refusing to commit")?;
+        let next = index_store::commit_next(&self.members, prev, entries)?;
+        self.staged.clear();
+        self.reserved.clear();
+        Ok(self.current.insert(next))
+    }
+
+    /// Plans a truncate against the current generation and, unless `dry_run`,
+    /// carries it out; that commits a new generation recording the cut.
+    pub fn truncate(&mut self, retention: &Retention, now: i64, dry_run: bool) -> Result<TruncatePlan> {
+        let current = self.current.as_ref().context("No IndexChunk loaded")?;
+        let plan = truncate::plan(&self.members, &self.cipher, current, retention, now)?;
+        if !dry_run {
+            let next = truncate::apply(&self.members, current, &plan)?;
+            self.current = Some(next);
+        }
+        Ok(plan)
+    }
+
+    /// Entries of the current generation with the staged files applied, to
+    /// be carried into the next commit.
+    pub fn current_entries(&self) -> Result<Vec<FileEntryT>> {
+        let mut entries = match &self.current {
+            Some(current) => parse_index_chunk(&current.data)?.unpack().entries.unwrap_or_default(),
+            None => Vec::new(),
+        };
+        for staged in self.staged.values() {
+            match entries.iter_mut().find(|e| e.name == staged.name) {
+                Some(existing) => *existing = staged.clone(),
+                None => entries.push(staged.clone()),
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// Opens the attached volume whose anchors carry this Master Key's fingerprint
+/// and loads its newest committed IndexChunk, verifying the hash chain behind it.
+pub fn open_matching(
+    keys: &KeyManager,
+    fingerprint: &[u8],
+    volumes: &BTreeMap<String, Vec<MemberDisk>>,
+) -> Result<FsManager> {
+    let mut matching = volumes
+        .iter()
+        .filter(|(_, members)| members.iter().all(|m| m.mk_fingerprint == fingerprint));
+    let (uuid, members) = match (matching.next(), matching.next()) {
+        (Some(found), None) => found,
+        (None, _) => anyhow::bail!("No attached volume belongs to this Master Key"),
+        (Some(_), Some(_)) => anyhow::bail!("More than one attached volume belongs to this Master Key"),
+    };
+    for m in members {
+        info!("Volume {}: {:?} hw_id={:#x} sector_size={}", uuid, m.device, m.hw_id, m.sector_size);
+    }
+
+    let data_key = keys
+        .derive_subkey(SubkeyPurpose::DataEncryption, uuid)
+        .map_err(|e| anyhow::anyhow!("Cannot derive data key: {:?}", e))?;
+    let mut fs = FsManager::open(uuid, members, &data_key)?;
+    if fs.load_latest_index_chunk()?.is_none() {
+        anyhow::bail!("Volume {} has no committed IndexChunk", uuid);
+    }
+    fs.verify_history()?;
+    Ok(fs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tuff_common::block_device::FileBlockDevice;
+    use tuff_common::data_chunk::read_chain;
+    use tuff_common::layout::{min_device_chunks, write_index_slot};
+    use tuff_common::schemas::build_minimal_index_chunk;
+
+    #[test]
+    fn staged_files_go_through_the_queue_and_publish_on_commit() {
+        let base = std::env::temp_dir().join(format!("tuff_fsm_{}", std::process::id()));
+        std::fs::create_dir_all(&base).unwrap();
+        let dev = FileBlockDevice::create(&base.join("disk.img"), min_device_chunks() + 8).unwrap();
+        write_index_slot(&dev, 1, &build_minimal_index_chunk("vol", 1).unwrap()).unwrap();
+        let mut members: MemberMap = BTreeMap::new();
+        members.insert(1, Box::new(dev));
+        let mut fs = FsManager {
+            volume_uuid: "vol".into(),
+            members,
+            cipher: ChunkCipher::new(&[3u8; 32]),
+            queue: WriteQueue::open(&base.join("queue")).unwrap(),
+            current: None,
+            staged: BTreeMap::new(),
+            reserved: BTreeSet::new(),
+        };
+        fs.load_latest_index_chunk().unwrap();
+
+        assert!(fs.stage_file(State::PendingOnly, "a", b"refused").is_err());
+        assert_eq!(fs.queue_depth(), 0);
+
+        let first = fs.stage_file(State::Normal, "a", b"first").unwrap().start_chunk_id;
+        assert_eq!(first, DATA_START);
+        let entries = fs.current_entries().unwrap();
+        fs.commit(&entries).unwrap();
+        assert_eq!(fs.queue_depth(), 0);
+
+        // The committed chain stays referenced, so a rewrite lands elsewhere.
+        let second = fs.stage_file(State::Normal, "a", b"second").unwrap().start_chunk_id;
+        assert_eq!(second, DATA_START + 1);
+        let other = fs.stage_file(State::Normal, "b", b"").unwrap().start_hw_id;
+        assert_eq!(other, 0, "an empty file has no chain");
+        let entries = fs.current_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        fs.commit(&entries).unwrap();
+
+        let start = ChunkAddr { hw_id: 1, chunk_id: second };
+        assert_eq!(read_chain(&fs.members, &fs.cipher, start, 6).unwrap(), b"second");
+        assert!(fs.staged.is_empty() && fs.reserved.is_empty());
+        std::fs::remove_dir_all(&base).unwrap();
+    }
 }
@@ -10,28 +10,61 @@ mod usb_monitor;
 mod fs_manager;
 mod events;
 mod mk_fingerprint;
+mod disk_probe;
+mod control;
+mod write_queue;
+mod shutdown;
+mod supervisor;
+mod uevent;
+mod config;
+mod console;
 
 use state_machine::{SystemState, State};
 use events::{TuffLogEntry, LogLevel, TuffEvent};
-use tuff_common::schemas::{build_minimal_index_chunk, validate_index_chunk};
 use mk_fingerprint::{verify_or_store_mk_fingerprint, FingerprintStatus};
+use tuff_crypto::key_manager::KeyManager;
+use tuff_common::paths::{CONTROL_SOCKET_PATH, MK_FINGERPRINT_PATH};
+use std::path::Path;
+
+/// Queued chunks written per main-loop turn in PendingOnly.
+const DRAIN_BATCH: usize = 64;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     env_logger::init();
-    if is_pid1() {
+    let pid1 = is_pid1();
+    if pid1 {
         if let Err(e) = early_boot_setup() {
             error!("Early boot setup failed: {}", e);
         }
+        shutdown::disable_cad();
     }
+    let mut signals = shutdown::ShutdownSignals::install()?;
+    let mut supervisor = supervisor::Supervisor::new()?;
 
     TuffLogEntry::new(
         LogLevel::Info,
         TuffEvent::SystemBoot { version: env!("CARGO_PKG_VERSION").to_string() },
     ).log();
 
+    let config = config::TuffdConfig::load();
+
     // 1. Initialize State Machine
     let mut state = SystemState::new();
+    let mut keys = KeyManager::new();
+    // The opened volume; its member devices stay open while it is held.
+    let mut volume: Option<fs_manager::FsManager> = None;
+    // Members found by the last probe, by volume UUID.
+    let mut volumes = disk_probe::probe_volumes_or_empty();
+    // The USB partition the loaded key came from, while a volume is open.
+    let mut key_watch: Option<usb_monitor::KeyWatch> = None;
+    // Set while PendingOnly drains the queue before locking on key removal.
+    let mut locking = false;
+    let mut key_search = usb_monitor::KeySearch::spawn(config.allow_legacy_key);
+    let mut control_rx = control::spawn(Path::new(CONTROL_SOCKET_PATH)).unwrap_or_else(|e| {
+        error!("Control socket unavailable: {:#}", e);
+        tokio::sync::mpsc::channel(1).1
+    });
 
     // 2. Transition to WAIT_KEY
     if state.current() == State::Init {
@@ -46,16 +79,93 @@ async fn main() -> Result<()> {
         ).log();
     }
 
+    if pid1 {
+        for helper in supervisor::default_helpers() {
+            supervisor.supervise(helper);
+        }
+    }
+
     info!("System is now in WAIT_KEY state. Listening for USB events...");
 
     // 3. Main Event Loop
     loop {
         match state.current() {
-            State::WaitKey => {
-                match usb_monitor::wait_for_key().await {
-                    Ok(Some((key, uuid))) => {
+            State::WaitKey => key_search.arm(usb_monitor::SearchMode::Now),
+            State::Warn if volume.is_none() => key_search.arm(usb_monitor::SearchMode::AfterChange),
+            _ => {}
+        }
+        let wake = tokio::select! {
+            action = signals.recv() => Wake::Shutdown(action),
+            event = supervisor.wait() => Wake::Supervisor(event),
+            Some(call) = control_rx.recv() => Wake::Control(call),
+            _ = key_removed(key_watch.as_mut()) => Wake::KeyRemoved,
+            found = key_search.next() => Wake::KeyFound(found),
+            _ = idle(state.current()) => Wake::Idle,
+        };
+        match wake {
+            Wake::Supervisor(event) => supervisor.handle(event),
+            Wake::Shutdown(action) => {
+                supervisor.stop_all().await;
+                shutdown::run(action, &mut state, &mut keys, &mut volume, pid1);
+                return Ok(());
+            }
+            Wake::Control(call) => {
+                let ctx = control::DaemonContext {
+                    state: &mut state,
+                    keys: &mut keys,
+                    volume: &mut volume,
+                    volumes: &mut volumes,
+                    key_watch: &mut key_watch,
+                    allow_legacy_key: config.allow_legacy_key,
+                    mk_fingerprint_path: Path::new(MK_FINGERPRINT_PATH),
+                };
+                let response = control::handle(call.request, ctx);
+                let _ = call.reply.send(response);
+            }
+            Wake::KeyRemoved => {
+                let watch = key_watch.take().expect("only woken while watching");
+                TuffLogEntry::new(
+                    LogLevel::Audit,
+                    TuffEvent::KeyRemoved {
+                        device: watch.device().to_string_lossy().to_string(),
+                        policy: config.key_removal,
+                    },
+                ).log();
+                locking = on_key_removed(config.key_removal, &mut state, &mut keys, &mut volume);
+            }
+            Wake::Idle => {}
+            Wake::KeyFound(found) => {
+                if state.current() == State::Warn && state.transition_to(State::WaitKey) {
+                    TuffLogEntry::new(
+                        LogLevel::Info,
+                        TuffEvent::StateTransition {
+                            from: State::Warn,
+                            to: State::WaitKey,
+                            reason: "Block devices changed; retrying the key".into(),
+                        },
+                    ).log();
+                }
+                if state.current() != State::WaitKey {
+                    // Left WaitKey some other way while the search ran.
+                    continue;
+                }
+                match found {
+                    Ok(found) => {
+                        let uuid = found.key_uuid.clone();
+                        if let Err(e) = keys.load_key(found.master_key.as_slice()) {
+                            TuffLogEntry::new(
+                                LogLevel::Warn,
+                                TuffEvent::KeyRejected {
+                                    device: found.device.to_string_lossy().to_string(),
+                                    reason: format!("Unusable key material: {:?}", e),
+                                },
+                            ).log();
+                            key_search.arm(usb_monitor::SearchMode::AfterChange);
+                            continue;
+                        }
                         info!("Key {} accepted.", uuid);
-                        match verify_or_store_mk_fingerprint(&key) {
+                        let fingerprint = keys.fingerprint().expect("key loaded above");
+                        match verify_or_store_mk_fingerprint(&fingerprint) {
                             Ok(FingerprintStatus::Matched) | Ok(FingerprintStatus::Stored) => {}
                             Ok(FingerprintStatus::Mismatch) => {
                                 state.transition_to(State::Freeze);
@@ -77,7 +187,6 @@ async fn main() -> Result<()> {
                                 continue;
                             }
                             Err(e) => {
-                                state.transition_to(State::Warn);
                                 TuffLogEntry::new(
                                     LogLevel::Error,
                                     TuffEvent::IoError {
@@ -85,72 +194,202 @@ async fn main() -> Result<()> {
                                         error: e.to_string(),
                                     },
                                 ).log();
-                                sleep(Duration::from_secs(5)).await;
+                                hold_in_warn(&mut state, &mut keys, "MK fingerprint check failed".into());
                                 continue;
                             }
                         }
-                        let fs = fs_manager::FsManager;
-                        let mut promote_normal = true;
-                        match fs.load_latest_index_chunk()? {
-                            Some(buf) => {
-                                if let Err(e) = validate_index_chunk(&buf) {
-                                    error!("IndexChunk validation failed: {}", e);
-                                    state.transition_to(State::Warn);
-                                    promote_normal = false;
-                                }
-                            }
-                            None => {
-                                info!("IndexChunk not found yet; creating placeholder.");
-                                let placeholder = build_minimal_index_chunk("tuff-volume", 1)?;
-                                if let Err(e) = fs.write_latest_index_chunk(&placeholder) {
-                                    error!("Failed to write placeholder IndexChunk: {}", e);
-                                    state.transition_to(State::Warn);
-                                    promote_normal = false;
-                                } else if let Ok(Some(back)) = fs.load_latest_index_chunk() {
-                                    if let Err(e) = validate_index_chunk(&back) {
-                                        error!("Post-write IndexChunk validation failed: {}", e);
-                                        state.transition_to(State::Warn);
-                                        promote_normal = false;
-                                    }
-                                } else {
-                                    error!("Post-write IndexChunk readback failed");
-                                    state.transition_to(State::Warn);
-                                    promote_normal = false;
+                        volumes = disk_probe::probe_volumes_or_empty();
+                        match fs_manager::open_matching(&keys, &fingerprint, &volumes) {
+                            Ok(fs) => {
+                                let current = fs.current().expect("opened with an index");
+                                info!(
+                                    "Volume {} at generation {} ({} replicas).",
+                                    fs.volume_uuid(),
+                                    current.generation,
+                                    current.replicas.len()
+                                );
+                                let queued = fs.queue_depth();
+                                volume = Some(fs);
+                                key_watch = Some(usb_monitor::KeyWatch::new(found.device));
+                                state.transition_to(State::Normal);
+                                TuffLogEntry::new(
+                                    LogLevel::Info,
+                                    TuffEvent::StateTransition {
+                                        from: State::WaitKey,
+                                        to: State::Normal,
+                                        reason: "Key authenticated".into(),
+                                    },
+                                ).log();
+                                if queued > 0 {
+                                    enter_pending_only(
+                                        &mut state,
+                                        format!("Replaying {} queued writes", queued),
+                                    );
                                 }
                             }
+                            Err(e) => hold_in_warn(&mut state, &mut keys, format!("{:#}", e)),
                         }
-                        if promote_normal {
-                            state.transition_to(State::Normal);
-                        }
-                        TuffLogEntry::new(
-                            LogLevel::Info,
-                            TuffEvent::StateTransition {
-                                from: State::WaitKey,
-                                to: State::Normal,
-                                reason: "Key authenticated".into(),
-                            },
-                        ).log();
                     }
                     Err(e) => {
-                        error!("USB Monitor error: {}", e);
-                        sleep(Duration::from_secs(5)).await;
-                    }
-                    Ok(None) => {
-                        // Should not happen with current wait_for_key impl as it loops internally
+                        error!("USB Monitor error: {:#}", e);
+                        key_search.arm(usb_monitor::SearchMode::AfterChange);
                     }
                 }
             }
-            State::Normal => {
-                sleep(Duration::from_secs(10)).await;
+        }
+        if let Some(fs) = volume.as_mut() {
+            let drained = if locking { State::WaitKey } else { State::Normal };
+            pump_write_queue(&mut state, fs, drained);
+        }
+        if locking && state.current() != State::PendingOnly {
+            // Drained (now WaitKey) or the replay failed (now Freeze): either
+            // way the volume is closed and the key must not outlive it.
+            locking = false;
+            keys.clear();
+            volume = None;
+        }
+    }
+}
+
+/// A key was accepted but no volume opens with it. Drops the key and waits in
+/// Warn for a disk or stick to come or go instead of rescanning the same one.
+fn hold_in_warn(state: &mut SystemState, keys: &mut KeyManager, reason: String) {
+    keys.clear();
+    if state.transition_to(State::Warn) {
+        TuffLogEntry::new(
+            LogLevel::Warn,
+            TuffEvent::StateTransition { from: State::WaitKey, to: State::Warn, reason },
+        ).log();
+    }
+}
+
+/// Applies the key removal policy. Returns true when the volume stays open in
+/// PendingOnly until its write queue drains; otherwise it is already locked.
+fn on_key_removed(
+    policy: config::KeyRemovalPolicy,
+    state: &mut SystemState,
+    keys: &mut KeyManager,
+    volume: &mut Option<fs_manager::FsManager>,
+) -> bool {
+    let from = state.current();
+    match (policy, from) {
+        (config::KeyRemovalPolicy::PendingOnly, State::Normal | State::PendingOnly) => {
+            if from == State::Normal {
+                enter_pending_only(state, "Key removed; draining write queue before lock".into());
             }
-            State::Freeze => {
-                error!("System FROZEN. Waiting for Admin intervention.");
-                sleep(Duration::from_secs(10)).await;
+            true
+        }
+        (_, State::Normal | State::PendingOnly) => {
+            state.transition_to(State::Freeze);
+            TuffLogEntry::new(
+                LogLevel::Warn,
+                TuffEvent::StateTransition {
+                    from,
+                    to: State::Freeze,
+                    reason: "Key removed; queued writes kept for the next unlock".into(),
+                },
+            ).log();
+            keys.clear();
+            *volume = None;
+            state.transition_to(State::WaitKey);
+            TuffLogEntry::new(
+                LogLevel::Info,
+                TuffEvent::StateTransition {
+                    from: State::Freeze,
+                    to: State::WaitKey,
+                    reason: "Volume locked".into(),
+                },
+            ).log();
+            false
+        }
+        _ => {
+            // Frozen for another reason: stay frozen, but drop the key.
+            keys.clear();
+            *volume = None;
+            false
+        }
+    }
+}
+
+async fn key_removed(watch: Option<&mut usb_monitor::KeyWatch>) {
+    match watch {
+        Some(watch) => watch.removed().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Moves Normal to PendingOnly when the queue backs up, and drains one batch
+/// per loop turn in PendingOnly, moving to `drained` once the queue is empty.
+fn pump_write_queue(state: &mut SystemState, fs: &mut fs_manager::FsManager, drained: State) {
+    match state.current() {
+        State::Normal if fs.queue_depth() >= write_queue::HIGH_WATERMARK => {
+            enter_pending_only(state, format!("Write queue at {} entries", fs.queue_depth()));
+        }
+        State::PendingOnly => {
+            if let Err(e) = fs.drain_queue(DRAIN_BATCH) {
+                state.transition_to(State::Freeze);
+                TuffLogEntry::new(
+                    LogLevel::Error,
+                    TuffEvent::StateTransition {
+                        from: State::PendingOnly,
+                        to: State::Freeze,
+                        reason: format!("Write queue replay failed: {:#}", e),
+                    },
+                ).log();
+                return;
             }
-            _ => {
-                sleep(Duration::from_secs(1)).await;
+            let depth = fs.queue_depth();
+            TuffLogEntry::new(
+                LogLevel::Info,
+                TuffEvent::WriteQueueDepth { volume_uuid: fs.volume_uuid().to_string(), depth },
+            ).log();
+            if depth == 0 {
+                state.transition_to(drained);
+                TuffLogEntry::new(
+                    LogLevel::Info,
+                    TuffEvent::StateTransition {
+                        from: State::PendingOnly,
+                        to: drained,
+                        reason: "Write queue drained".into(),
+                    },
+                ).log();
             }
         }
+        _ => {}
+    }
+}
+
+fn enter_pending_only(state: &mut SystemState, reason: String) {
+    state.transition_to(State::PendingOnly);
+    TuffLogEntry::new(
+        LogLevel::Warn,
+        TuffEvent::StateTransition { from: State::Normal, to: State::PendingOnly, reason },
+    ).log();
+}
+
+enum Wake {
+    Shutdown(shutdown::ShutdownAction),
+    Supervisor(supervisor::SupervisorEvent),
+    Control(control::ControlCall),
+    KeyRemoved,
+    /// Result of the armed key search (WaitKey, or Warn without a volume).
+    KeyFound(anyhow::Result<usb_monitor::UnlockedKey>),
+    Idle,
+}
+
+/// What the daemon does between control requests in `state`.
+async fn idle(state: State) {
+    match state {
+        // The key search wakes the loop.
+        State::WaitKey | State::Warn => std::future::pending().await,
+        State::Normal => sleep(Duration::from_secs(10)).await,
+        // Drain batches back to back, still answering control requests in between.
+        State::PendingOnly => tokio::task::yield_now().await,
+        State::Freeze => {
+            error!("System FROZEN. Waiting for Admin intervention (tuffctl unfreeze).");
+            sleep(Duration::from_secs(10)).await;
+        }
+        _ => sleep(Duration::from_secs(1)).await,
     }
 }
 
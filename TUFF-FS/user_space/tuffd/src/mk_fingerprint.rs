@@ -1,9 +1,9 @@
 use anyhow::{Context, Result};
-use sha2::{Digest, Sha256};
 use std::fs;
 use std::path::Path;
 
 use tuff_common::paths::MK_FINGERPRINT_PATH;
+use tuff_crypto::key_manager::FINGERPRINT_LEN;
 
 pub enum FingerprintStatus {
     Stored,
@@ -11,8 +11,9 @@ pub enum FingerprintStatus {
     Mismatch,
 }
 
-pub fn verify_or_store_mk_fingerprint(key: &[u8]) -> Result<FingerprintStatus> {
-    let fingerprint = sha256_hex(key);
+/// `fingerprint` is the canonical `tuff_crypto::key_manager::mk_fingerprint` digest.
+pub fn verify_or_store_mk_fingerprint(fingerprint: &[u8; FINGERPRINT_LEN]) -> Result<FingerprintStatus> {
+    let fingerprint = to_hex(fingerprint);
     let path = Path::new(MK_FINGERPRINT_PATH);
 
     if path.exists() {
@@ -34,12 +35,23 @@ pub fn verify_or_store_mk_fingerprint(key: &[u8]) -> Result<FingerprintStatus> {
     Ok(FingerprintStatus::Stored)
 }
 
-fn sha256_hex(data: &[u8]) -> String {
-    let mut hasher = Sha256::new();
-    hasher.update(data);
-    let digest = hasher.finalize();
+/// Compares against the record at `path` without ever writing it. A missing
+/// record is a rejection: only the normal key search may create one.
+pub fn verify_mk_fingerprint(path: &Path, fingerprint: &[u8; FINGERPRINT_LEN]) -> Result<()> {
+    if !path.exists() {
+        anyhow::bail!("No MK fingerprint is recorded at {}", path.display());
+    }
+    let existing = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    if existing.trim() != to_hex(fingerprint) {
+        anyhow::bail!("Master Key does not match the recorded MK fingerprint");
+    }
+    Ok(())
+}
+
+fn to_hex(digest: &[u8]) -> String {
     let mut out = String::with_capacity(digest.len() * 2);
-    for b in digest {
+    for &b in digest {
         out.push(hex_char(b >> 4));
         out.push(hex_char(b & 0x0f));
     }
@@ -0,0 +1,128 @@
+use anyhow::Result;
+use log::{error, info, warn};
+use nix::mount::{umount2, MntFlags};
+use nix::sys::reboot::{reboot, set_cad_enabled, RebootMode};
+use nix::sys::signal::Signal as NixSignal;
+use nix::unistd::sync;
+use std::fs;
+use tokio::signal::unix::{signal, Signal, SignalKind};
+use tuff_common::paths::CONTROL_SOCKET_PATH;
+use tuff_crypto::key_manager::KeyManager;
+
+use crate::events::{TuffLogEntry, LogLevel, TuffEvent};
+use crate::fs_manager::FsManager;
+use crate::state_machine::{State, SystemState};
+
+/// Mounted by `early_boot_setup`; released in reverse order.
+const EARLY_MOUNTS: [&str; 4] = ["/dev/pts", "/dev", "/sys", "/proc"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownAction {
+    PowerOff,
+    Restart,
+}
+
+/// SIGTERM and SIGPWR power off; SIGINT (ctrl-alt-del, once CAD is disabled)
+/// restarts.
+pub struct ShutdownSignals {
+    term: Signal,
+    pwr: Signal,
+    int: Signal,
+}
+
+impl ShutdownSignals {
+    pub fn install() -> Result<Self> {
+        Ok(Self {
+            term: signal(SignalKind::terminate())?,
+            pwr: signal(SignalKind::from_raw(NixSignal::SIGPWR as i32))?,
+            int: signal(SignalKind::interrupt())?,
+        })
+    }
+
+    pub async fn recv(&mut self) -> ShutdownAction {
+        tokio::select! {
+            _ = self.term.recv() => ShutdownAction::PowerOff,
+            _ = self.pwr.recv() => ShutdownAction::PowerOff,
+            _ = self.int.recv() => ShutdownAction::Restart,
+        }
+    }
+}
+
+/// As PID 1, route ctrl-alt-del to SIGINT instead of an immediate reboot.
+pub fn disable_cad() {
+    if let Err(e) = set_cad_enabled(false) {
+        warn!("Cannot disable ctrl-alt-del: {}", e);
+    }
+}
+
+/// Takes the daemon down. As PID 1 this ends in `reboot(2)` and never returns.
+pub fn run(
+    action: ShutdownAction,
+    state: &mut SystemState,
+    keys: &mut KeyManager,
+    volume: &mut Option<FsManager>,
+    pid1: bool,
+) {
+    let from = state.current();
+    state.transition_to(State::Shutdown);
+    TuffLogEntry::new(
+        LogLevel::Info,
+        TuffEvent::StateTransition {
+            from,
+            to: State::Shutdown,
+            reason: format!("{:?} requested", action),
+        },
+    ).log();
+
+    if let Some(mut fs) = volume.take() {
+        // A frozen or degraded volume is left exactly as it is.
+        if matches!(from, State::Normal | State::PendingOnly) {
+            let result = fs.current_entries().and_then(|entries| {
+                fs.commit(&entries).map(|c| (c.generation, entries.len()))
+            });
+            match result {
+                Ok((generation, entries)) => TuffLogEntry::new(
+                    LogLevel::Audit,
+                    TuffEvent::IndexCommitted {
+                        volume_uuid: fs.volume_uuid().to_string(),
+                        generation,
+                        entries,
+                    },
+                ).log(),
+                Err(e) => TuffLogEntry::new(
+                    LogLevel::Error,
+                    TuffEvent::IoError {
+                        context: "Final commit before shutdown failed".into(),
+                        error: format!("{:#}", e),
+                    },
+                ).log(),
+            }
+        }
+    }
+    keys.clear();
+    let _ = fs::remove_file(CONTROL_SOCKET_PATH);
+
+    if !pid1 {
+        info!("Shutdown complete.");
+        return;
+    }
+
+    sync();
+    for target in EARLY_MOUNTS {
+        if let Err(e) = umount2(target, MntFlags::MNT_DETACH) {
+            warn!("Unmount of {} failed: {}", target, e);
+        }
+    }
+    sync();
+
+    let mode = match action {
+        ShutdownAction::PowerOff => RebootMode::RB_POWER_OFF,
+        ShutdownAction::Restart => RebootMode::RB_AUTOBOOT,
+    };
+    let err = reboot(mode).unwrap_err();
+    error!("reboot(2) failed: {}", err);
+    // PID 1 must never exit; wait for the power to go.
+    loop {
+        std::thread::park();
+    }
+}
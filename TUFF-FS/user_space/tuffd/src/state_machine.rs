@@ -1,16 +1,6 @@
 use log::{info, warn};
-use serde::{Deserialize, Serialize};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
-pub enum State {
-    Init,
-    WaitKey,
-    Normal,
-    Warn,
-    Freeze,
-    PendingOnly,
-    Shutdown,
-}
+
+pub use tuff_common::state::State;
 
 pub struct SystemState {
     current: State,
@@ -47,6 +37,9 @@ impl SystemState {
             // WaitKey goes to Normal upon success
             (State::WaitKey, State::Normal) => true,
             (State::WaitKey, State::Freeze) => true,
+            (State::WaitKey, State::Shutdown) => true,
+            // ...or to Warn when the key is good but no volume opens with it
+            (State::WaitKey, State::Warn) => true,
 
             // Normal operation
             (State::Normal, State::Warn) => true,
@@ -57,14 +50,22 @@ impl SystemState {
             // Warn can recover or worsen
             (State::Warn, State::Normal) => true,
             (State::Warn, State::Freeze) => true,
+            (State::Warn, State::Shutdown) => true,
+            // No volume open: retry the key search once the hardware changes
+            (State::Warn, State::WaitKey) => true,
 
             // Freeze is a trap. Only explicit Admin intervention or Shutdown can exit.
             (State::Freeze, State::Normal) => true,
             (State::Freeze, State::Shutdown) => true,
+            // ...or pulling the key, which locks the volume.
+            (State::Freeze, State::WaitKey) => true,
 
             // PendingOnly (Queue processing without new writes)
             (State::PendingOnly, State::Normal) => true,
             (State::PendingOnly, State::Freeze) => true,
+            (State::PendingOnly, State::Shutdown) => true,
+            // Key removed: the queue has drained and the volume is locked.
+            (State::PendingOnly, State::WaitKey) => true,
 
             _ => false,
         }
@@ -111,4 +112,42 @@ mod tests {
         assert!(!state.transition_to(State::Shutdown));
         assert!(!state.transition_to(State::PendingOnly));
     }
+
+    #[test]
+    fn shutdown_reachable_once_booted() {
+        let mut state = SystemState::new();
+        assert!(state.transition_to(State::WaitKey));
+        assert!(state.transition_to(State::Shutdown));
+
+        let mut state = SystemState::new();
+        assert!(state.transition_to(State::WaitKey));
+        assert!(state.transition_to(State::Normal));
+        assert!(state.transition_to(State::PendingOnly));
+        assert!(state.transition_to(State::Shutdown));
+        assert!(!state.transition_to(State::Normal));
+    }
+
+    #[test]
+    fn key_removal_returns_to_waitkey() {
+        let mut state = SystemState::new();
+        assert!(state.transition_to(State::WaitKey));
+        assert!(state.transition_to(State::Normal));
+        assert!(!state.transition_to(State::WaitKey));
+        assert!(state.transition_to(State::PendingOnly));
+        assert!(state.transition_to(State::WaitKey));
+
+        assert!(state.transition_to(State::Normal));
+        assert!(state.transition_to(State::Freeze));
+        assert!(state.transition_to(State::WaitKey));
+    }
+
+    #[test]
+    fn unopenable_volume_warns_until_retry() {
+        let mut state = SystemState::new();
+        assert!(state.transition_to(State::WaitKey));
+        assert!(state.transition_to(State::Warn));
+        assert!(state.transition_to(State::WaitKey));
+        assert!(state.transition_to(State::Normal));
+        assert!(!state.transition_to(State::WaitKey));
+    }
 }
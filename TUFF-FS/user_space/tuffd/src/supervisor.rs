@@ -0,0 +1,342 @@
+// Child supervision for tuffd as PID 1.
+//
+// Every SIGCHLD triggers a non-blocking waitpid(-1) loop, so orphans reparented
+// to PID 1 are reaped along with our own helpers. A supervised helper that exits
+// is restarted after a backoff that doubles per quick exit, up to MAX_BACKOFF.
+// At shutdown helpers get STOP_GRACE to exit on SIGTERM before SIGKILL.
+use anyhow::{Context, Result};
+use log::{debug, warn};
+use nix::errno::Errno;
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::Pid;
+use std::fs::OpenOptions;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::time::{sleep_until, Duration, Instant};
+
+use crate::events::{TuffLogEntry, LogLevel, TuffEvent};
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// A helper that stayed up this long starts over from `INITIAL_BACKOFF`.
+const STABLE_RUN: Duration = Duration::from_secs(30);
+const STOP_GRACE: Duration = Duration::from_secs(5);
+/// After SIGKILL only the reaping is left to wait for.
+const KILL_GRACE: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+pub struct ChildSpec {
+    pub name: String,
+    pub program: PathBuf,
+    pub args: Vec<String>,
+    /// Attach stdin/stdout/stderr to /dev/console.
+    pub console: bool,
+}
+
+struct Supervised {
+    spec: ChildSpec,
+    pid: Option<Pid>,
+    started: Instant,
+    backoff: Duration,
+    restart_at: Option<Instant>,
+}
+
+impl Supervised {
+    fn new(spec: ChildSpec) -> Self {
+        Self { spec, pid: None, started: Instant::now(), backoff: INITIAL_BACKOFF, restart_at: None }
+    }
+
+    /// Records an exit at `now` and schedules the restart; returns its delay.
+    fn exited(&mut self, now: Instant) -> Duration {
+        self.pid = None;
+        if now.duration_since(self.started) >= STABLE_RUN {
+            self.backoff = INITIAL_BACKOFF;
+        }
+        self.schedule_restart(now)
+    }
+
+    fn schedule_restart(&mut self, now: Instant) -> Duration {
+        let delay = self.backoff;
+        self.restart_at = Some(now + delay);
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+        delay
+    }
+}
+
+pub enum SupervisorEvent {
+    ChildSignal,
+    RestartDue,
+}
+
+pub struct Supervisor {
+    children: Vec<Supervised>,
+    sigchld: tokio::signal::unix::Signal,
+    /// Set by `stop_all`: exits are reaped but no longer restarted.
+    stopping: bool,
+}
+
+impl Supervisor {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            children: Vec::new(),
+            sigchld: signal(SignalKind::child()).context("Cannot watch SIGCHLD")?,
+            stopping: false,
+        })
+    }
+
+    /// Starts `spec` now and keeps it running.
+    pub fn supervise(&mut self, spec: ChildSpec) {
+        let mut child = Supervised::new(spec);
+        start(&mut child);
+        self.children.push(child);
+    }
+
+    /// Waits for a child to change state or a restart to fall due.
+    pub async fn wait(&mut self) -> SupervisorEvent {
+        let next_restart = self.children.iter().filter_map(|c| c.restart_at).min();
+        tokio::select! {
+            _ = self.sigchld.recv() => SupervisorEvent::ChildSignal,
+            _ = sleep_until(next_restart.unwrap_or_else(far_future)), if next_restart.is_some() => {
+                SupervisorEvent::RestartDue
+            }
+        }
+    }
+
+    pub fn handle(&mut self, event: SupervisorEvent) {
+        match event {
+            SupervisorEvent::ChildSignal => self.reap(),
+            SupervisorEvent::RestartDue => {
+                let now = Instant::now();
+                for child in &mut self.children {
+                    if child.restart_at.is_some_and(|at| at <= now) {
+                        start(child);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Stops restarting helpers, asks them to exit and waits for the reaper;
+    /// whatever is still running after `STOP_GRACE` is killed.
+    pub async fn stop_all(&mut self) {
+        self.stop_within(STOP_GRACE).await;
+    }
+
+    async fn stop_within(&mut self, grace: Duration) {
+        self.stopping = true;
+        for child in &mut self.children {
+            child.restart_at = None;
+        }
+        self.signal_all(Signal::SIGTERM);
+        if !self.wait_for_exits(grace).await {
+            self.signal_all(Signal::SIGKILL);
+            if !self.wait_for_exits(KILL_GRACE).await {
+                warn!("Helpers still running after SIGKILL; shutting down anyway");
+            }
+        }
+        self.children.clear();
+    }
+
+    fn signal_all(&self, sig: Signal) {
+        for child in &self.children {
+            if let Some(pid) = child.pid {
+                if let Err(e) = kill(pid, sig) {
+                    debug!("{:?} to {} ({}) failed: {}", sig, child.spec.name, pid, e);
+                }
+            }
+        }
+    }
+
+    /// Reaps until no supervised helper runs; false if `limit` passes first.
+    async fn wait_for_exits(&mut self, limit: Duration) -> bool {
+        let deadline = Instant::now() + limit;
+        loop {
+            self.reap();
+            if self.children.iter().all(|c| c.pid.is_none()) {
+                return true;
+            }
+            if Instant::now() >= deadline {
+                return false;
+            }
+            tokio::select! {
+                _ = self.sigchld.recv() => {}
+                _ = sleep_until(deadline) => {}
+            }
+        }
+    }
+
+    fn reap(&mut self) {
+        loop {
+            let status = match waitpid(Pid::from_raw(-1), Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => return,
+                Ok(status) => status,
+                Err(Errno::EINTR) => continue,
+                Err(e) => {
+                    warn!("waitpid failed: {}", e);
+                    return;
+                }
+            };
+            let (pid, description) = match status {
+                WaitStatus::Exited(pid, code) => (pid, format!("exit code {}", code)),
+                WaitStatus::Signaled(pid, sig, _) => (pid, format!("killed by {:?}", sig)),
+                // Stops and continues are not exits.
+                _ => continue,
+            };
+
+            let stopping = self.stopping;
+            let supervised = self.children.iter_mut().find(|c| c.pid == Some(pid));
+            let (name, restart_in_secs) = match supervised {
+                Some(child) if stopping => {
+                    child.pid = None;
+                    (child.spec.name.clone(), None)
+                }
+                Some(child) => {
+                    let delay = child.exited(Instant::now());
+                    (child.spec.name.clone(), Some(delay.as_secs()))
+                }
+                None => ("orphan".to_string(), None),
+            };
+            TuffLogEntry::new(
+                if restart_in_secs.is_some() { LogLevel::Warn } else { LogLevel::Info },
+                TuffEvent::ChildExited {
+                    name,
+                    pid: pid.as_raw(),
+                    status: description,
+                    restart_in_secs,
+                },
+            ).log();
+        }
+    }
+}
+
+fn start(child: &mut Supervised) {
+    child.restart_at = None;
+    child.started = Instant::now();
+    match spawn(&child.spec) {
+        Ok(pid) => {
+            child.pid = Some(pid);
+            TuffLogEntry::new(
+                LogLevel::Info,
+                TuffEvent::ChildSpawned { name: child.spec.name.clone(), pid: pid.as_raw() },
+            ).log();
+        }
+        Err(e) => {
+            let delay = child.schedule_restart(Instant::now());
+            TuffLogEntry::new(
+                LogLevel::Error,
+                TuffEvent::IoError {
+                    context: format!("Failed to start {} (retry in {}s)", child.spec.name, delay.as_secs()),
+                    error: format!("{:#}", e),
+                },
+            ).log();
+        }
+    }
+}
+
+fn spawn(spec: &ChildSpec) -> Result<Pid> {
+    let mut cmd = Command::new(&spec.program);
+    cmd.args(&spec.args);
+    if spec.console {
+        let console = || OpenOptions::new().read(true).write(true).open("/dev/console");
+        cmd.stdin(Stdio::from(console()?))
+            .stdout(Stdio::from(console()?))
+            .stderr(Stdio::from(console()?));
+    }
+    // The handle is dropped without waiting: the SIGCHLD reaper collects the exit.
+    let child = cmd
+        .spawn()
+        .with_context(|| format!("Cannot execute {}", spec.program.display()))?;
+    Ok(Pid::from_raw(child.id() as i32))
+}
+
+fn far_future() -> Instant {
+    Instant::now() + Duration::from_secs(86_400)
+}
+
+/// Helpers tuffd keeps alive when it runs as PID 1.
+pub fn default_helpers() -> Vec<ChildSpec> {
+    let mut helpers = Vec::new();
+    let shell = PathBuf::from("/bin/sh");
+    if cfg!(debug_assertions) && shell.exists() {
+        helpers.push(ChildSpec {
+            name: "recovery-shell".into(),
+            program: shell,
+            args: Vec::new(),
+            console: true,
+        });
+    }
+    helpers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(program: &str, args: &[&str]) -> ChildSpec {
+        ChildSpec {
+            name: "test-helper".into(),
+            program: PathBuf::from(program),
+            args: args.iter().map(|a| a.to_string()).collect(),
+            console: false,
+        }
+    }
+
+    #[test]
+    fn quick_exits_back_off_and_a_stable_run_resets() {
+        let mut child = Supervised::new(spec("/bin/true", &[]));
+        let mut delays = Vec::new();
+        for _ in 0..8 {
+            child.started = Instant::now();
+            delays.push(child.exited(child.started + Duration::from_secs(1)).as_secs());
+        }
+        assert_eq!(delays, vec![1, 2, 4, 8, 16, 32, 60, 60]);
+
+        child.started = Instant::now();
+        let now = child.started + STABLE_RUN;
+        assert_eq!(child.exited(now), INITIAL_BACKOFF);
+        assert_eq!(child.restart_at, Some(now + INITIAL_BACKOFF));
+    }
+
+    #[test]
+    fn failed_start_is_retried_with_backoff() {
+        let mut child = Supervised::new(spec("/nonexistent/tuff-helper", &[]));
+        start(&mut child);
+        assert!(child.pid.is_none());
+        assert!(child.restart_at.is_some());
+        start(&mut child);
+        assert_eq!(child.backoff, INITIAL_BACKOFF * 4);
+    }
+
+    // Reaping uses waitpid(-1), so everything that spawns real children lives in
+    // one test; parallel tests would collect each other's exits.
+    #[tokio::test]
+    async fn exits_are_reaped_and_stop_all_kills_stragglers() {
+        let mut supervisor = Supervisor::new().unwrap();
+        supervisor.supervise(spec("/bin/true", &[]));
+        let reaped = async {
+            while supervisor.children[0].pid.is_some() {
+                let event = supervisor.wait().await;
+                supervisor.handle(event);
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), reaped).await.unwrap();
+        assert!(supervisor.children[0].restart_at.is_some());
+        assert_eq!(supervisor.children[0].backoff, INITIAL_BACKOFF * 2);
+
+        let mut supervisor = Supervisor::new().unwrap();
+        supervisor.supervise(spec("/bin/sh", &["-c", "sleep 30"]));
+        supervisor.supervise(spec("/bin/sh", &["-c", "trap '' TERM; sleep 30 & wait"]));
+        let pids: Vec<Pid> = supervisor.children.iter().filter_map(|c| c.pid).collect();
+        assert_eq!(pids.len(), 2);
+        // Let the second shell install its trap.
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        supervisor.stop_within(Duration::from_millis(300)).await;
+        assert!(supervisor.children.is_empty());
+        for pid in pids {
+            assert_eq!(kill(pid, None), Err(Errno::ESRCH), "{} is still around", pid);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+// Kernel uevents over NETLINK_KOBJECT_UEVENT, without udev.
+//
+// A kernel uevent datagram is "<action>@<devpath>\0" followed by
+// NUL-separated KEY=VALUE pairs (ACTION, DEVPATH, SUBSYSTEM, DEVNAME, ...).
+use anyhow::{Context, Result};
+use nix::errno::Errno;
+use nix::sys::socket::{
+    bind, recv, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol, SockType,
+};
+use std::os::fd::{AsRawFd, OwnedFd};
+use tokio::io::unix::AsyncFd;
+
+/// Multicast group the kernel itself sends to (udev re-broadcasts on group 2).
+const KERNEL_GROUP: u32 = 1;
+const MAX_UEVENT: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UeventAction {
+    Add,
+    Remove,
+    Change,
+    Other(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Uevent {
+    pub action: UeventAction,
+    pub devpath: String,
+    pub subsystem: Option<String>,
+    pub devname: Option<String>,
+    pub devtype: Option<String>,
+}
+
+impl Uevent {
+    pub fn is_block(&self) -> bool {
+        self.subsystem.as_deref() == Some("block")
+    }
+}
+
+/// Parses one kernel uevent datagram; `None` for anything else (e.g. udev's
+/// "libudev" messages).
+pub fn parse(buf: &[u8]) -> Option<Uevent> {
+    let mut fields = buf.split(|&b| b == 0).filter(|f| !f.is_empty());
+    let summary = std::str::from_utf8(fields.next()?).ok()?;
+    let (_, summary_path) = summary.split_once('@')?;
+
+    let mut event = Uevent {
+        action: UeventAction::Other(String::new()),
+        devpath: summary_path.to_string(),
+        subsystem: None,
+        devname: None,
+        devtype: None,
+    };
+    let mut action = None;
+    for field in fields {
+        let Ok(field) = std::str::from_utf8(field) else { continue };
+        let Some((key, value)) = field.split_once('=') else { continue };
+        match key {
+            "ACTION" => action = Some(value.to_string()),
+            "DEVPATH" => event.devpath = value.to_string(),
+            "SUBSYSTEM" => event.subsystem = Some(value.to_string()),
+            "DEVNAME" => event.devname = Some(value.to_string()),
+            "DEVTYPE" => event.devtype = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    event.action = match action?.as_str() {
+        "add" => UeventAction::Add,
+        "remove" => UeventAction::Remove,
+        "change" => UeventAction::Change,
+        other => UeventAction::Other(other.to_string()),
+    };
+    Some(event)
+}
+
+pub struct UeventListener {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl UeventListener {
+    pub fn open() -> Result<Self> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Datagram,
+            SockFlag::SOCK_CLOEXEC | SockFlag::SOCK_NONBLOCK,
+            SockProtocol::NetlinkKObjectUEvent,
+        )
+        .context("Cannot open NETLINK_KOBJECT_UEVENT socket")?;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, KERNEL_GROUP))
+            .context("Cannot bind to kernel uevents")?;
+        Ok(Self { fd: AsyncFd::new(fd)? })
+    }
+
+    /// Waits for the next well-formed kernel uevent. `None` means the kernel
+    /// dropped events because the socket buffer overflowed (ENOBUFS): the
+    /// socket stays usable, but whatever was being watched must be rescanned.
+    pub async fn next(&mut self) -> Result<Option<Uevent>> {
+        let mut buf = vec![0u8; MAX_UEVENT];
+        loop {
+            let mut guard = self.fd.readable().await?;
+            let read = guard.try_io(|fd| {
+                recv(fd.as_raw_fd(), &mut buf, MsgFlags::empty()).map_err(std::io::Error::from)
+            });
+            match read {
+                Ok(Ok(len)) => {
+                    if let Some(event) = parse(&buf[..len]) {
+                        return Ok(Some(event));
+                    }
+                }
+                Ok(Err(e)) if e.raw_os_error() == Some(Errno::ENOBUFS as i32) => return Ok(None),
+                Ok(Err(e)) => return Err(e.into()),
+                Err(_would_block) => continue,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_kernel_block_event() {
+        let raw = b"add@/devices/pci0000:00/usb1/1-1/block/sdb/sdb1\0ACTION=add\0\
+DEVPATH=/devices/pci0000:00/usb1/1-1/block/sdb/sdb1\0SUBSYSTEM=block\0DEVNAME=sdb1\0\
+DEVTYPE=partition\0SEQNUM=4242\0";
+        let event = parse(raw).unwrap();
+        assert_eq!(event.action, UeventAction::Add);
+        assert!(event.is_block());
+        assert_eq!(event.devname.as_deref(), Some("sdb1"));
+        assert_eq!(event.devtype.as_deref(), Some("partition"));
+        assert!(event.devpath.ends_with("/sdb1"));
+    }
+
+    #[test]
+    fn ignores_udev_and_garbage() {
+        assert!(parse(b"libudev\0\xfe\xed\xca\xfe").is_none());
+        assert!(parse(b"remove@/devices/x\0SUBSYSTEM=block\0").is_none());
+        assert!(parse(b"").is_none());
+    }
+}
@@ -1,23 +1,140 @@
 use anyhow::{Context, Result};
-use log::{info, debug};
+use log::{info, debug, warn};
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::Duration;
 use crate::events::{TuffLogEntry, LogLevel, TuffEvent};
+use crate::console;
+use crate::uevent::{UeventAction, UeventListener};
+use tuff_common::paths::SYSTEM_UUID_PATH;
+use tuff_crypto::key_file::{self, KeyFileError};
+use tokio::sync::mpsc;
+use zeroize::Zeroizing;
+
 use nix::mount::{mount, umount2, MntFlags, MsFlags};
 use nix::errno::Errno;
 
-/// Polls /dev/disk/by-id for USB devices containing a valid TUFF Key.
-/// Returns the raw 32-byte key and the UUID (filename) if found.
-pub async fn wait_for_key() -> Result<Option<(Vec<u8>, String)>> {
-    info!("Starting USB Key polling...");
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+const RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+const SETTLE_DELAY: Duration = Duration::from_millis(300);
+const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+
+/// A key found on a USB stick.
+struct FoundKey {
+    /// Key file contents: a wrapped key, or a legacy raw key when allowed.
+    file: Zeroizing<Vec<u8>>,
+    /// File stem of the key file.
+    key_uuid: String,
+    /// Partition the key was read from.
+    device: PathBuf,
+}
+
+/// A found key after its passphrase was accepted.
+pub struct UnlockedKey {
+    pub master_key: Zeroizing<Vec<u8>>,
+    pub key_uuid: String,
+    pub device: PathBuf,
+}
+
+/// When an armed key search starts scanning.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    /// Right away.
+    Now,
+    /// Once a block device is added or removed, so a stick that was just
+    /// rejected is not scanned again until something changes.
+    AfterChange,
+}
+
+/// The USB key search, run by one task for the life of the daemon so that
+/// control requests and signals do not restart it. The task also asks for the
+/// passphrase, so the prompt never holds up the main loop. The main loop arms
+/// it and gets one result per arming; a rejected key is logged and the search
+/// waits for the next block device change.
+pub struct KeySearch {
+    arm: mpsc::Sender<SearchMode>,
+    results: mpsc::Receiver<Result<UnlockedKey>>,
+    armed: bool,
+}
+
+impl KeySearch {
+    /// Starts the search task; it scans nothing until armed.
+    pub fn spawn(allow_legacy: bool) -> Self {
+        let (arm, mut armed) = mpsc::channel::<SearchMode>(1);
+        let (found, results) = mpsc::channel(1);
+        tokio::spawn(async move {
+            let mut uevents = match UeventListener::open() {
+                Ok(listener) => Some(listener),
+                Err(e) => {
+                    warn!("Uevent listener unavailable, polling instead: {:#}", e);
+                    None
+                }
+            };
+            while let Some(mut mode) = armed.recv().await {
+                let result = loop {
+                    if mode == SearchMode::AfterChange {
+                        wait_for_block_change(&mut uevents).await;
+                    }
+                    let key = match wait_for_key(&mut uevents, allow_legacy).await {
+                        Ok(key) => key,
+                        Err(e) => break Err(e),
+                    };
+                    match unwrap_key(&key).await {
+                        Ok(master_key) => {
+                            break Ok(UnlockedKey { master_key, key_uuid: key.key_uuid, device: key.device })
+                        }
+                        Err(e) => {
+                            TuffLogEntry::new(
+                                LogLevel::Warn,
+                                TuffEvent::KeyRejected {
+                                    device: key.device.to_string_lossy().to_string(),
+                                    reason: format!("{:#}", e),
+                                },
+                            ).log();
+                            mode = SearchMode::AfterChange;
+                        }
+                    }
+                };
+                if found.send(result).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Self { arm, results, armed: false }
+    }
+
+    /// Starts a search unless one is already under way.
+    pub fn arm(&mut self, mode: SearchMode) {
+        if !self.armed && self.arm.try_send(mode).is_ok() {
+            self.armed = true;
+        }
+    }
+
+    /// The result of the armed search; never completes while unarmed.
+    pub async fn next(&mut self) -> Result<UnlockedKey> {
+        if !self.armed {
+            std::future::pending::<()>().await;
+        }
+        let result = self.results.recv().await;
+        self.armed = false;
+        result.context("USB key search task has stopped")?
+    }
+}
 
+/// Waits for a USB device carrying a valid TUFF Key.
+/// Rescans whenever the kernel announces a new block device; without a uevent
+/// socket it falls back to polling every `POLL_INTERVAL`.
+/// Only `TUFF_KEYS/<system_uuid>.key` is accepted, so one stick may carry
+/// keys for several machines.
+/// Returns the key file, its UUID (filename) and the partition it is on.
+async fn wait_for_key(uevents: &mut Option<UeventListener>, allow_legacy: bool) -> Result<FoundKey> {
+    info!("Starting USB Key search...");
+    let sys_uuid = system_uuid()?;
     let mut attempt_count = 0u64;
 
-    // Polling loop
     loop {
-        // Log "Searching" event every ~30 seconds (15 attempts * 2 sec)
-        if attempt_count % 15 == 0 {
+        // Log "Searching" event every ~30 seconds while polling
+        if attempt_count.is_multiple_of(15) {
             TuffLogEntry::new(
                 LogLevel::Info,
                 TuffEvent::KeySearch {
@@ -32,7 +149,7 @@ pub async fn wait_for_key() -> Result<Option<(Vec<u8>, String)>> {
         for device_path in usb_devices {
             debug!("Checking candidate device: {:?}", device_path);
 
-            if let Ok(Some((key, uuid))) = check_device_for_key(&device_path) {
+            if let Ok(Some((file, uuid))) = check_device_for_key(&device_path, &sys_uuid, allow_legacy) {
                 TuffLogEntry::new(
                     LogLevel::Audit,
                     TuffEvent::KeyDetected {
@@ -40,13 +157,152 @@ pub async fn wait_for_key() -> Result<Option<(Vec<u8>, String)>> {
                         key_uuid: uuid.clone(),
                     },
                 ).log();
-                return Ok(Some((key, uuid)));
+                return Ok(FoundKey { file, key_uuid: uuid, device: device_path });
+            }
+        }
+
+        match uevents.as_mut() {
+            Some(listener) => {
+                if let Err(e) = wait_for_block_add(listener).await {
+                    warn!("Uevent listener failed, polling instead: {:#}", e);
+                    *uevents = None;
+                }
+            }
+            None => tokio::time::sleep(POLL_INTERVAL).await,
+        }
+    }
+}
+
+/// Returns once a block device appears, or after `RESCAN_INTERVAL` regardless
+/// (a missed event must not stall the search).
+async fn wait_for_block_add(listener: &mut UeventListener) -> Result<()> {
+    let deadline = tokio::time::sleep(RESCAN_INTERVAL);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            event = listener.next() => {
+                let Some(event) = event? else {
+                    debug!("Uevents were dropped; rescanning");
+                    return Ok(());
+                };
+                if event.is_block() && event.action == UeventAction::Add {
+                    debug!("Block device added: {:?}", event.devname);
+                    // Give the kernel a moment to finish partition scanning.
+                    tokio::time::sleep(SETTLE_DELAY).await;
+                    return Ok(());
+                }
+            }
+            _ = &mut deadline => return Ok(()),
+        }
+    }
+}
+
+/// Returns once a block device is added or removed, or after `RESCAN_INTERVAL`
+/// without a uevent socket.
+async fn wait_for_block_change(uevents: &mut Option<UeventListener>) {
+    let Some(listener) = uevents.as_mut() else {
+        tokio::time::sleep(RESCAN_INTERVAL).await;
+        return;
+    };
+    loop {
+        match listener.next().await {
+            Ok(Some(e)) if e.is_block() && matches!(e.action, UeventAction::Add | UeventAction::Remove) => {
+                tokio::time::sleep(SETTLE_DELAY).await;
+                return;
+            }
+            // Events were dropped; one of them may have been the change.
+            Ok(None) => return,
+            Ok(Some(_)) => {}
+            Err(e) => {
+                warn!("Uevent listener failed, polling instead: {:#}", e);
+                *uevents = None;
+                tokio::time::sleep(RESCAN_INTERVAL).await;
+                return;
+            }
+        }
+    }
+}
+
+/// Scans once, without waiting or prompting, for the partition holding this
+/// machine's key file.
+pub fn find_key_device(allow_legacy: bool) -> Result<Option<PathBuf>> {
+    let sys_uuid = system_uuid()?;
+    for device_path in scan_usb_devices()? {
+        if let Ok(Some(_)) = check_device_for_key(&device_path, &sys_uuid, allow_legacy) {
+            return Ok(Some(device_path));
+        }
+    }
+    Ok(None)
+}
+
+/// Watches the partition a key was read from until it disappears.
+pub struct KeyWatch {
+    device: PathBuf,
+    /// Kernel names whose removal takes the key with it: the partition and the
+    /// disk it is on.
+    names: Vec<String>,
+    uevents: Option<UeventListener>,
+}
+
+impl KeyWatch {
+    pub fn new(device: PathBuf) -> Self {
+        let uevents = UeventListener::open()
+            .map_err(|e| warn!("Uevent listener unavailable, polling for key removal: {:#}", e))
+            .ok();
+        let mut names = Vec::new();
+        if let Some(name) = device.file_name().and_then(|n| n.to_str()) {
+            names.extend(parent_disk(Path::new(SYS_CLASS_BLOCK), name));
+            names.push(name.to_string());
+        }
+        Self { device, names, uevents }
+    }
+
+    pub fn device(&self) -> &Path {
+        &self.device
+    }
+
+    /// Returns once the key device is gone. The device node is also polled,
+    /// so a missed uevent delays detection by at most `POLL_INTERVAL`.
+    pub async fn removed(&mut self) {
+        loop {
+            if !self.device.exists() {
+                return;
+            }
+            let Some(listener) = self.uevents.as_mut() else {
+                tokio::time::sleep(POLL_INTERVAL).await;
+                continue;
+            };
+            tokio::select! {
+                event = listener.next() => match event {
+                    Ok(Some(e)) if e.is_block() && e.action == UeventAction::Remove => {
+                        // Removing the whole disk removes its partitions too.
+                        let gone = e.devname.as_ref().is_some_and(|dev| self.names.contains(dev));
+                        if gone {
+                            return;
+                        }
+                    }
+                    // Dropped events are covered by the existence check above.
+                    Ok(_) => {}
+                    Err(e) => {
+                        warn!("Uevent listener failed, polling for key removal: {:#}", e);
+                        self.uevents = None;
+                    }
+                },
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
             }
         }
+    }
+}
 
-        // Wait before next scan
-        tokio::time::sleep(Duration::from_secs(2)).await;
+/// The disk that block device `name` is a partition of, read from sysfs;
+/// `None` for a whole disk.
+fn parent_disk(class_block: &Path, name: &str) -> Option<String> {
+    let entry = class_block.join(name);
+    if !entry.join("partition").exists() {
+        return None;
     }
+    let sysfs_path = fs::canonicalize(&entry).ok()?;
+    Some(sysfs_path.parent()?.file_name()?.to_str()?.to_string())
 }
 
 fn scan_usb_devices() -> Result<Vec<PathBuf>> {
@@ -102,7 +358,54 @@ fn list_partitions(block_path: &Path, base: &str) -> Result<Vec<PathBuf>> {
     Ok(parts)
 }
 
-fn check_device_for_key(device_path: &Path) -> Result<Option<(Vec<u8>, String)>> {
+/// Reads the DMI product UUID the key files are named after.
+fn system_uuid() -> Result<String> {
+    let uuid = fs::read_to_string(SYSTEM_UUID_PATH)
+        .with_context(|| format!("Cannot read system UUID from {}", SYSTEM_UUID_PATH))?;
+    let uuid = uuid.trim();
+    if uuid.is_empty() {
+        anyhow::bail!("{} is empty", SYSTEM_UUID_PATH);
+    }
+    Ok(uuid.to_string())
+}
+
+/// Passphrase prompts per key file before it is rejected.
+const PASSPHRASE_ATTEMPTS: usize = 3;
+
+/// Recovers the Master Key from a found key file, asking for the passphrase
+/// on the console when it is wrapped.
+async fn unwrap_key(found: &FoundKey) -> Result<Zeroizing<Vec<u8>>> {
+    if key_file::is_legacy(&found.file) {
+        // Only returned by the scan when legacy keys are allowed.
+        return Ok(found.file.clone());
+    }
+    for _ in 0..PASSPHRASE_ATTEMPTS {
+        let prompt = format!("Passphrase for TUFF key {}: ", found.key_uuid);
+        let passphrase = tokio::task::spawn_blocking(move || console::read_passphrase(&prompt))
+            .await
+            .context("Passphrase prompt panicked")??;
+        let file = found.file.clone();
+        let uuid = found.key_uuid.clone();
+        // Argon2 is deliberately slow; keep it off the runtime threads.
+        let unwrapped = tokio::task::spawn_blocking(move || {
+            key_file::unwrap(&file, passphrase.as_bytes(), &uuid)
+        })
+        .await
+        .context("Key unwrap panicked")?;
+        match unwrapped {
+            Ok(key) => return Ok(Zeroizing::new(key.to_vec())),
+            Err(KeyFileError::Authentication) => warn!("Wrong passphrase for key {}", found.key_uuid),
+            Err(e) => anyhow::bail!("Cannot unwrap key file: {:?}", e),
+        }
+    }
+    anyhow::bail!("No valid passphrase after {} attempts", PASSPHRASE_ATTEMPTS)
+}
+
+fn check_device_for_key(
+    device_path: &Path,
+    sys_uuid: &str,
+    allow_legacy: bool,
+) -> Result<Option<(Zeroizing<Vec<u8>>, String)>> {
     let mount_point = Path::new("/mnt/tuff_key_check");
     fs::create_dir_all(mount_point)?;
 
@@ -119,34 +422,57 @@ fn check_device_for_key(device_path: &Path) -> Result<Option<(Vec<u8>, String)>>
     }
 
     // 2. Search for Key
-    let result = (|| -> Result<Option<(Vec<u8>, String)>> {
+    let result = (|| -> Result<Option<(Zeroizing<Vec<u8>>, String)>> {
         let key_dir = mount_point.join("TUFF_KEYS");
         if !key_dir.exists() {
             return Ok(None);
         }
 
+        let device = device_path.to_string_lossy().to_string();
+        let mut foreign = Vec::new();
         for entry in fs::read_dir(key_dir)? {
             let entry = entry?;
             let path = entry.path();
-            if let Some(ext) = path.extension() {
-                if ext == "key" {
-                    // Validate size (32 bytes)
-                    let metadata = fs::metadata(&path)?;
-                    if metadata.len() == 32 {
-                        let key_data = fs::read(&path)?;
-                        let file_stem = path.file_stem().unwrap().to_string_lossy().to_string();
-                        return Ok(Some((key_data, file_stem)));
-                    } else {
-                        TuffLogEntry::new(
-                            LogLevel::Warn,
-                            TuffEvent::KeyRejected {
-                                device: device_path.to_string_lossy().to_string(),
-                                reason: format!("Invalid key size: {} bytes", metadata.len()),
-                            },
-                        ).log();
+            if path.extension().is_none_or(|ext| ext != "key") {
+                continue;
+            }
+            let file_stem = path.file_stem().unwrap().to_string_lossy().to_string();
+            // DMI UUIDs are hex; tolerate a differently cased file name.
+            if !file_stem.eq_ignore_ascii_case(sys_uuid) {
+                foreign.push(file_stem);
+                continue;
+            }
+            let data = Zeroizing::new(fs::read(&path)?);
+            let rejected = if key_file::is_legacy(&data) {
+                if allow_legacy {
+                    return Ok(Some((data, file_stem)));
+                }
+                "Legacy raw key refused (allow_legacy_key is off)".to_string()
+            } else {
+                match key_file::parse_header(&data) {
+                    Ok((header, _)) if header.system_uuid.eq_ignore_ascii_case(sys_uuid) => {
+                        return Ok(Some((data, file_stem)));
                     }
+                    Ok((header, _)) => {
+                        format!("Key file is bound to {}, not this machine", header.system_uuid)
+                    }
+                    Err(e) => format!("Invalid key file ({} bytes): {:?}", data.len(), e),
                 }
-            }
+            };
+            TuffLogEntry::new(
+                LogLevel::Warn,
+                TuffEvent::KeyRejected { device: device.clone(), reason: rejected },
+            ).log();
+        }
+        // Only worth reporting when the stick holds no key for this machine.
+        for stem in foreign {
+            TuffLogEntry::new(
+                LogLevel::Warn,
+                TuffEvent::KeyRejected {
+                    device: device.clone(),
+                    reason: format!("Key {} is bound to another machine", stem),
+                },
+            ).log();
         }
         Ok(None)
     })();
@@ -183,3 +509,30 @@ fn mount_readonly(device_path: &Path, mount_point: &Path) -> Result<()> {
     }
     Err(anyhow::anyhow!("No supported filesystem for USB key"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn parent_disk_comes_from_sysfs_not_the_name() {
+        let root = std::env::temp_dir().join(format!("tuff_sysfs_{}", std::process::id()));
+        let devices = root.join("devices/usb1/1-1/block");
+        let class = root.join("class/block");
+        fs::create_dir_all(devices.join("sdb/sdb1")).unwrap();
+        fs::create_dir_all(devices.join("sdb1x")).unwrap();
+        fs::create_dir_all(&class).unwrap();
+        fs::write(devices.join("sdb/sdb1/partition"), "1\n").unwrap();
+        symlink(devices.join("sdb"), class.join("sdb")).unwrap();
+        symlink(devices.join("sdb/sdb1"), class.join("sdb1")).unwrap();
+        symlink(devices.join("sdb1x"), class.join("sdb1x")).unwrap();
+
+        assert_eq!(parent_disk(&class, "sdb1").as_deref(), Some("sdb"));
+        // A whole disk has no parent, whatever its name looks like.
+        assert_eq!(parent_disk(&class, "sdb"), None);
+        assert_eq!(parent_disk(&class, "sdb1x"), None);
+        assert_eq!(parent_disk(&class, "sdc"), None);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
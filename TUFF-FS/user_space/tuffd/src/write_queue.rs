@@ -0,0 +1,218 @@
+// Write-ahead queue for sealed data chunks.
+//
+// Each queued write is one file `<seq>.wq` holding
+//   "TUFFWQ01" | hw_id u64 LE | chunk_id u64 LE | sealed chunk | SHA-256 of the above
+// written to a temporary name, fsynced and renamed, so a file is either complete
+// or absent. Draining writes the chunks to the members, flushes them and only then
+// removes the files; a crash in between replays the same bytes again.
+// An entry that fails its checks when the queue opens is renamed to
+// `<seq>.wq.bad` and left for inspection; the rest of the queue still replays.
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use tuff_common::block_device::CHUNK_SIZE;
+use tuff_common::data_chunk::{ChunkAddr, MemberMap};
+
+use crate::events::{LogLevel, TuffEvent, TuffLogEntry};
+
+const ENTRY_MAGIC: &[u8; 8] = b"TUFFWQ01";
+const ENTRY_LEN: usize = 8 + 8 + 8 + CHUNK_SIZE + 32;
+
+/// Queue depth at which Normal hands over to PendingOnly until drained.
+pub const HIGH_WATERMARK: usize = 1024;
+
+pub struct WriteQueue {
+    dir: PathBuf,
+    pending: VecDeque<(u64, ChunkAddr)>,
+    /// Never reused, not even after a full drain: a quarantined `<seq>.wq.bad`
+    /// keeps its number, and a reused one would collide with it.
+    next_seq: u64,
+}
+
+impl WriteQueue {
+    /// Opens (or creates) the queue in `dir` and checks every persisted entry,
+    /// quarantining the ones that fail.
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+        let mut pending = Vec::new();
+        let mut next_seq = 0;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+            if name.ends_with(".tmp") {
+                // Leftover temporary file from an interrupted enqueue.
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            let stem = name.strip_suffix(".wq").or_else(|| name.strip_suffix(".wq.bad"));
+            let Some(seq) = stem.and_then(|n| u64::from_str_radix(n, 16).ok()) else {
+                continue;
+            };
+            next_seq = next_seq.max(seq.saturating_add(1));
+            if name.ends_with(".bad") {
+                continue;
+            }
+            match read_entry(&path) {
+                Ok((addr, _)) => pending.push((seq, addr)),
+                Err(e) => quarantine(&path, &e)?,
+            }
+        }
+        pending.sort_by_key(|(seq, _)| *seq);
+        Ok(Self { dir: dir.to_path_buf(), pending: pending.into(), next_seq })
+    }
+
+    pub fn depth(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Persists one sealed chunk; it is durable when this returns.
+    pub fn enqueue(&mut self, addr: ChunkAddr, sealed: &[u8]) -> Result<()> {
+        if sealed.len() != CHUNK_SIZE {
+            anyhow::bail!("sealed chunk must be {} bytes, got {}", CHUNK_SIZE, sealed.len());
+        }
+        let mut buf = Vec::with_capacity(ENTRY_LEN);
+        buf.extend_from_slice(ENTRY_MAGIC);
+        buf.extend_from_slice(&addr.hw_id.to_le_bytes());
+        buf.extend_from_slice(&addr.chunk_id.to_le_bytes());
+        buf.extend_from_slice(sealed);
+        let digest = Sha256::digest(&buf);
+        buf.extend_from_slice(&digest);
+
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let tmp = self.dir.join(format!("{:016x}.tmp", seq));
+        let mut file = File::create(&tmp)?;
+        file.write_all(&buf)?;
+        file.sync_all()?;
+        fs::rename(&tmp, self.entry_path(seq))?;
+        File::open(&self.dir)?.sync_all()?;
+
+        self.pending.push_back((seq, addr));
+        Ok(())
+    }
+
+    /// Writes up to `max` queued chunks in order; returns how many were written.
+    pub fn drain(&mut self, members: &MemberMap, max: usize) -> Result<usize> {
+        let batch: Vec<(u64, ChunkAddr)> = self.pending.iter().take(max).copied().collect();
+        for (seq, addr) in &batch {
+            let (_, sealed) = read_entry(&self.entry_path(*seq))?;
+            let dev = members
+                .get(&addr.hw_id)
+                .with_context(|| format!("queued write for unknown hw_id {:#x}", addr.hw_id))?;
+            dev.write_chunk(addr.chunk_id, &sealed)?;
+        }
+        for dev in members.values() {
+            dev.flush()?;
+        }
+        for (seq, _) in &batch {
+            fs::remove_file(self.entry_path(*seq))?;
+            self.pending.pop_front();
+        }
+        File::open(&self.dir)?.sync_all()?;
+        Ok(batch.len())
+    }
+
+    fn entry_path(&self, seq: u64) -> PathBuf {
+        self.dir.join(format!("{:016x}.wq", seq))
+    }
+}
+
+fn quarantine(path: &Path, error: &anyhow::Error) -> Result<()> {
+    let mut bad = path.as_os_str().to_owned();
+    bad.push(".bad");
+    TuffLogEntry::new(
+        LogLevel::Error,
+        TuffEvent::IoError {
+            context: format!("Queued write {} skipped", path.display()),
+            error: format!("{:#}", error),
+        },
+    ).log();
+    fs::rename(path, &bad).with_context(|| format!("Failed to quarantine {}", path.display()))
+}
+
+fn read_entry(path: &Path) -> Result<(ChunkAddr, Vec<u8>)> {
+    let buf = fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    if buf.len() != ENTRY_LEN || &buf[..8] != ENTRY_MAGIC {
+        anyhow::bail!("{} is not a queued write", path.display());
+    }
+    let (body, digest) = buf.split_at(ENTRY_LEN - 32);
+    if Sha256::digest(body).as_slice() != digest {
+        anyhow::bail!("{} is corrupt (checksum mismatch)", path.display());
+    }
+    let hw_id = u64::from_le_bytes(body[8..16].try_into().unwrap());
+    let chunk_id = u64::from_le_bytes(body[16..24].try_into().unwrap());
+    Ok((ChunkAddr { hw_id, chunk_id }, body[24..].to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use tuff_common::block_device::FileBlockDevice;
+
+    #[test]
+    fn queue_survives_reopen_and_drains_in_order() {
+        let base = std::env::temp_dir().join(format!("tuff_wq_{}", std::process::id()));
+        let dir = base.join("queue");
+        let img = base.join("disk.img");
+        fs::create_dir_all(&base).unwrap();
+        let mut members: MemberMap = BTreeMap::new();
+        members.insert(7, Box::new(FileBlockDevice::create(&img, 4).unwrap()));
+
+        let addr = ChunkAddr { hw_id: 7, chunk_id: 2 };
+        let mut queue = WriteQueue::open(&dir).unwrap();
+        queue.enqueue(addr, &[1u8; CHUNK_SIZE]).unwrap();
+        queue.enqueue(addr, &[2u8; CHUNK_SIZE]).unwrap();
+        queue.enqueue(ChunkAddr { hw_id: 7, chunk_id: 3 }, &[3u8; CHUNK_SIZE]).unwrap();
+        drop(queue);
+
+        let mut queue = WriteQueue::open(&dir).unwrap();
+        assert_eq!(queue.depth(), 3);
+        assert_eq!(queue.drain(&members, 2).unwrap(), 2);
+        assert_eq!(queue.depth(), 1);
+        let mut chunk = vec![0u8; CHUNK_SIZE];
+        members[&7].read_chunk(2, &mut chunk).unwrap();
+        assert_eq!(chunk, vec![2u8; CHUNK_SIZE], "later write must win");
+
+        assert_eq!(WriteQueue::open(&dir).unwrap().depth(), 1);
+        assert_eq!(queue.drain(&members, usize::MAX).unwrap(), 1);
+        assert_eq!(WriteQueue::open(&dir).unwrap().depth(), 0);
+
+        // A damaged or truncated entry is set aside, never replayed, and does
+        // not keep the rest of the queue from opening.
+        let mut queue = WriteQueue::open(&dir).unwrap();
+        queue.enqueue(addr, &[4u8; CHUNK_SIZE]).unwrap();
+        queue.enqueue(addr, &[5u8; CHUNK_SIZE]).unwrap();
+        queue.enqueue(ChunkAddr { hw_id: 7, chunk_id: 3 }, &[6u8; CHUNK_SIZE]).unwrap();
+        let damaged = queue.entry_path(queue.pending[0].0);
+        let mut raw = fs::read(&damaged).unwrap();
+        raw[100] ^= 1;
+        fs::write(&damaged, raw).unwrap();
+        let truncated = queue.entry_path(queue.pending[1].0);
+        let raw = fs::read(&truncated).unwrap();
+        fs::write(&truncated, &raw[..raw.len() / 2]).unwrap();
+
+        let mut queue = WriteQueue::open(&dir).unwrap();
+        assert_eq!(queue.depth(), 1);
+        assert!(!damaged.exists() && !truncated.exists());
+        assert!(dir.join(format!("{}.bad", damaged.file_name().unwrap().to_str().unwrap())).exists());
+        assert_eq!(WriteQueue::open(&dir).unwrap().depth(), 1, "quarantined entries stay out");
+        assert_eq!(queue.drain(&members, usize::MAX).unwrap(), 1);
+        members[&7].read_chunk(2, &mut chunk).unwrap();
+        assert_eq!(chunk, vec![2u8; CHUNK_SIZE], "damaged writes must not reach the disk");
+        members[&7].read_chunk(3, &mut chunk).unwrap();
+        assert_eq!(chunk, vec![6u8; CHUNK_SIZE]);
+
+        // Fully drained, the next entry still numbers past every earlier one,
+        // quarantined ones included.
+        let mut queue = WriteQueue::open(&dir).unwrap();
+        queue.enqueue(addr, &[7u8; CHUNK_SIZE]).unwrap();
+        let bad_seq = |path: &Path| u64::from_str_radix(path.file_stem().unwrap().to_str().unwrap(), 16).unwrap();
+        assert!(queue.pending[0].0 > bad_seq(&damaged).max(bad_seq(&truncated)));
+        assert_eq!(WriteQueue::open(&dir).unwrap().depth(), 1);
+        fs::remove_dir_all(&base).unwrap();
+    }
+}